@@ -0,0 +1,267 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Persistent cache of distfile hashes.
+ *
+ * In a bulk build the same large distfiles are verified over and over by
+ * every package that uses them.  If MKTOOL_CHECKSUM_CACHE is set then hashes
+ * are recorded against the (dev, inode, size, mtime) of the file, and any
+ * later lookup with identical metadata is trusted without reading the file.
+ * Any modification to the file changes its metadata and so invalidates the
+ * entry.
+ *
+ * MKTOOL_CHECKSUM_CACHE should be set to the path of the cache file, e.g.
+ * ${DISTDIR}/.mktool-cache.  If set but empty then a default location under
+ * ${XDG_CACHE_HOME} (or ~/.cache) is used.
+ *
+ * The file format is one entry per line:
+ *
+ *   <dev> <inode> <size> <mtime_ns> <algorithm> <hash>
+ *
+ * Concurrent processes may share the same file.  Each process merges its new
+ * entries with the current file contents and atomically replaces it, so in
+ * the worst case an entry is lost and simply recalculated next time.
+ */
+
+use crate::hasher::Algorithm;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Key {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_ns: i128,
+    digest: Algorithm,
+}
+
+impl Key {
+    fn new(meta: &fs::Metadata, digest: Algorithm) -> Key {
+        Key {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            size: meta.size(),
+            mtime_ns: i128::from(meta.mtime()) * 1_000_000_000
+                + i128::from(meta.mtime_nsec()),
+            digest,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<Key, String>,
+    /* Entries added since load that need to be written out. */
+    added: HashMap<Key, String>,
+}
+
+#[derive(Debug)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+fn parse(contents: &str) -> HashMap<Key, String> {
+    let mut map = HashMap::new();
+    for line in contents.lines() {
+        let v: Vec<&str> = line.split_whitespace().collect();
+        let [dev, ino, size, mtime_ns, digest, hash] = v[..] else {
+            continue;
+        };
+        let (Ok(dev), Ok(ino), Ok(size), Ok(mtime_ns), Ok(digest)) = (
+            dev.parse(),
+            ino.parse(),
+            size.parse(),
+            mtime_ns.parse(),
+            Algorithm::from_str(digest),
+        ) else {
+            continue;
+        };
+        map.insert(Key { dev, ino, size, mtime_ns, digest }, hash.to_string());
+    }
+    map
+}
+
+impl HashCache {
+    /*
+     * Return a cache if enabled by MKTOOL_CHECKSUM_CACHE.  Failure to read an
+     * existing cache is not fatal, we just start with an empty one.
+     */
+    pub fn from_env() -> Option<HashCache> {
+        let path = match env::var_os("MKTOOL_CHECKSUM_CACHE") {
+            Some(p) if !p.is_empty() => PathBuf::from(p),
+            Some(_) => default_path()?,
+            None => return None,
+        };
+        Some(HashCache::open(path))
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> HashCache {
+        let path = path.as_ref().to_path_buf();
+        let map = match fs::read_to_string(&path) {
+            Ok(s) => parse(&s),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!(
+                        "WARNING: unable to read checksum cache {}: {e}",
+                        path.display()
+                    );
+                }
+                HashMap::new()
+            }
+        };
+        HashCache {
+            path,
+            entries: Mutex::new(Entries { map, added: HashMap::new() }),
+        }
+    }
+
+    pub fn get(
+        &self,
+        meta: &fs::Metadata,
+        digest: Algorithm,
+    ) -> Option<String> {
+        let entries = self.entries.lock().ok()?;
+        entries.map.get(&Key::new(meta, digest)).cloned()
+    }
+
+    pub fn insert(&self, meta: &fs::Metadata, digest: Algorithm, hash: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            let key = Key::new(meta, digest);
+            entries.map.insert(key, hash.to_string());
+            entries.added.insert(key, hash.to_string());
+        }
+    }
+
+    /*
+     * Write any new entries back to disk, merging with whatever is there
+     * now in case another process has updated it since we loaded it.
+     */
+    pub fn save(&self) -> io::Result<()> {
+        let Ok(entries) = self.entries.lock() else {
+            return Ok(());
+        };
+        if entries.added.is_empty() {
+            return Ok(());
+        }
+        let mut map = match fs::read_to_string(&self.path) {
+            Ok(s) => parse(&s),
+            Err(_) => HashMap::new(),
+        };
+        map.extend(entries.added.iter().map(|(k, v)| (*k, v.clone())));
+
+        if let Some(dir) = self.path.parent() {
+            if dir.as_os_str() != "" {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(format!(".{}", process::id()));
+        let tmp = PathBuf::from(tmp);
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        for (k, hash) in &map {
+            writeln!(
+                out,
+                "{} {} {} {} {} {}",
+                k.dev, k.ino, k.size, k.mtime_ns, k.digest, hash
+            )?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp, &self.path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+    }
+
+    /*
+     * Save and warn on failure, for callers that shouldn't fail because of
+     * a cache problem.
+     */
+    pub fn save_or_warn(&self) {
+        if let Err(e) = self.save() {
+            eprintln!(
+                "WARNING: unable to write checksum cache {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME") {
+        Some(p) if !p.is_empty() => PathBuf::from(p),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("mktool").join("checksums"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkgsrc::digest::Digest;
+
+    const SHA1: Algorithm = Algorithm::Pkgsrc(Digest::SHA1);
+
+    #[test]
+    fn test_cache() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("distfile");
+        let cachefile = dir.path().join("sub/cache");
+        fs::write(&file, "hello\n")?;
+        let meta = fs::metadata(&file)?;
+
+        let cache = HashCache::open(&cachefile);
+        assert_eq!(cache.get(&meta, SHA1), None);
+        cache.insert(&meta, SHA1, "abcd");
+        cache.insert(&meta, Algorithm::BLAKE3, "ef01");
+        assert_eq!(cache.get(&meta, SHA1), Some("abcd".to_string()));
+        assert_eq!(cache.get(&meta, Algorithm::SHA3_256), None);
+        cache.save()?;
+
+        /*
+         * Reload from disk, and ensure any change to the file invalidates
+         * the entry.
+         */
+        let cache = HashCache::open(&cachefile);
+        assert_eq!(cache.get(&meta, SHA1), Some("abcd".to_string()));
+        assert_eq!(
+            cache.get(&meta, Algorithm::BLAKE3),
+            Some("ef01".to_string())
+        );
+        fs::write(&file, "hello again\n")?;
+        let meta = fs::metadata(&file)?;
+        assert_eq!(cache.get(&meta, SHA1), None);
+
+        /*
+         * Garbage is ignored.
+         */
+        fs::write(&cachefile, "1 2 3\nx 1 2 3 SHA1 abcd\n1 2 3 4 BOGUS ab\n")?;
+        let cache = HashCache::open(&cachefile);
+        assert!(
+            cache.entries.lock().map(|e| e.map.is_empty()).unwrap_or(false)
+        );
+        Ok(())
+    }
+}
@@ -16,404 +16,837 @@
 
 extern crate glob;
 
-use crate::scrub_ctrl;
-use clap::Args;
-use std::collections::HashSet;
+mod checks;
+mod classify;
+mod lexer;
+mod makefile;
+mod patch;
+mod suppress;
+
+use crate::{build_thread_pool, scrub_ctrl};
+use checks::{
+    CHECKS, Check, Edit, Functions, Line, Severity, find_check, is_probe,
+    print_explanation,
+};
+use clap::{Args, ValueEnum};
+use classify::{Hashbang, hashbang, is_sh_name};
+use lexer::{Loc, LogicalLine, lex};
+use makefile::{is_makefile, recipes};
+use patch::{Patch, patch_name};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use suppress::{Suppressions, line_hash, parse_inline};
 use walkdir::WalkDir;
 
 #[derive(Args, Debug)]
-pub struct Cmd {}
+pub struct Cmd {
+    #[arg(short = 'd', value_name = "dir", default_value = ".")]
+    #[arg(help = "Directory to check, usually WRKSRC")]
+    dir: PathBuf,
+
+    #[arg(short = 'I', value_name = "input")]
+    #[arg(help = "Read files to check from input file (\"-\" for stdin)")]
+    input: Option<PathBuf>,
+
+    #[arg(short = 'P', long, value_name = "patchdir")]
+    #[arg(help = "Directory containing patches (or \"PATCHDIR\" env var)")]
+    patchdir: Option<PathBuf>,
+
+    #[arg(long, value_name = "glob", value_parser = glob::Pattern::new)]
+    #[arg(help = "Skip files matching glob (or CHECK_PORTABILITY_SKIP)")]
+    skip: Vec<glob::Pattern>,
+
+    #[arg(long, value_name = "glob", value_parser = glob::Pattern::new)]
+    #[arg(
+        help = "Check files matching glob as shell scripts (or CHECK_PORTABILITY_SCRIPTS)"
+    )]
+    script: Vec<glob::Pattern>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Also check Makefile recipes (or CHECK_PORTABILITY_EXPERIMENTAL=yes)"
+    )]
+    experimental: bool,
+
+    #[arg(long, value_name = "file")]
+    #[arg(help = "Read suppressions from file")]
+    suppressions: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "fix")]
+    #[arg(help = "Print a suppressions file entry for each match")]
+    print_suppressions: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    #[arg(help = "Output format for matches")]
+    format: OutputFormat,
+
+    #[arg(long)]
+    #[arg(help = "Fix any matches that can be, writing patches to PATCHDIR")]
+    fix: bool,
+
+    #[arg(short = 'j', value_name = "jobs")]
+    #[arg(help = "Maximum number of threads (or \"MKTOOL_JOBS\" env var)")]
+    jobs: Option<usize>,
+
+    #[arg(value_name = "file")]
+    #[arg(
+        help = "Files or directories to check, relative to -d (default: all)"
+    )]
+    files: Vec<PathBuf>,
+}
 
-fn check_random(line: &str) -> bool {
-    let mut rv = false;
-    let bytes = line.as_bytes();
-    for (start, _) in line.match_indices("$RANDOM") {
-        let next = start + "$RANDOM".len();
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// check-portability.sh compatible output
+    Text,
+    /// One JSON object per line
+    Json,
+    /// SARIF 2.1.0 log
+    Sarif,
+}
 
-        /*
-         * $RANDOM mixed with PID ($$) is commonly found in GNU configure
-         * scripts, and because they are always executed using a compatible
-         * shell then are considered acceptable.  Turning this off produces
-         * lots of false positives in e.g. config.guess.
-         */
-        if start >= 3 && &bytes[start - 3..start] == b"$$-" {
-            return false;
-        }
-        if bytes.get(next..next + 3) == Some(b"-$$") {
-            return false;
+/*
+ * List of file extensions to skip.  These are plain strings rather than
+ * adding to skipglob as it's faster.  Based on the lists in
+ * check-portability.sh but with some additions.
+ */
+const SKIPEXT: &[&str] = &[
+    "~",
+    ".1",
+    ".3",
+    ".C",
+    ".a",
+    ".ac",
+    ".c",
+    ".cc",
+    ".css",
+    ".cxx",
+    ".docbook",
+    ".dtd",
+    ".el",
+    ".f",
+    ".gif",
+    ".gn",
+    ".go",
+    ".gz",
+    ".h",
+    ".hpp",
+    ".htm",
+    ".html",
+    ".hxx",
+    ".idl",
+    ".inc",
+    ".jpg",
+    ".js",
+    ".json",
+    ".kicad_mod",
+    ".m4",
+    ".map",
+    ".md",
+    ".mo",
+    ".ogg",
+    ".orig",
+    ".page",
+    ".php",
+    ".pl",
+    ".png",
+    ".po",
+    ".properties",
+    ".py",
+    ".rb",
+    ".result",
+    ".svg",
+    ".test",
+    ".tfm",
+    ".ts",
+    ".txt",
+    ".vf",
+    ".xml",
+    ".xpm",
+];
+
+/*
+ * A check that matched a line.
+ */
+struct Finding {
+    path: PathBuf,
+    lineno: usize,
+    /* Location of the construct that matched, within the logical line. */
+    loc: Loc,
+    text: String,
+    check: &'static Check,
+    /* Changes to the file that would fix this match. */
+    edits: Vec<Edit>,
+}
+
+impl Finding {
+    fn print(&self) {
+        let level = match self.check.severity {
+            Severity::Warning => "WARNING",
+            Severity::Error => "ERROR",
+        };
+        eprintln!(
+            "{level}: [check-portability] => Found {}:",
+            self.check.found
+        );
+        eprintln!(
+            "{level}: [check-portability] {}:{}: {}",
+            self.path.display(),
+            self.lineno,
+            scrub_ctrl(&self.text)
+        );
+        print_explanation(self.check);
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.check.id,
+            "severity": self.check.severity.as_str(),
+            "path": self.path.to_string_lossy(),
+            "line": self.loc.lineno,
+            "column": self.loc.column,
+            "snippet": scrub_ctrl(&self.text),
+        })
+    }
+}
+
+/*
+ * Print one JSON object per line for each finding.
+ */
+fn print_json(findings: &[Finding]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    for f in findings {
+        writeln!(stdout, "{}", f.json())?;
+    }
+    stdout.flush()
+}
+
+/*
+ * Percent-encode a relative path for use as a SARIF artifact URI.
+ */
+fn sarif_uri(path: &Path) -> String {
+    let mut uri = String::new();
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{b:02X}"));
         }
+    }
+    uri
+}
 
-        /*
-         * Trailing A-Z_, i.e. a variable that starts "$RANDOM.." such as
-         * $RANDOMIZE is considered acceptable, but only if there is no bare
-         * $RANDOM elsewhere on the line, so continue to other matches.
-         */
-        if let Some(&b) = bytes.get(next) {
-            if b.is_ascii_uppercase() || b == b'_' {
+/*
+ * Print a SARIF log, with every check included as a rule so that results
+ * can refer to them by index.
+ */
+fn print_sarif(findings: &[Finding]) -> io::Result<()> {
+    let rules: Vec<serde_json::Value> = CHECKS
+        .iter()
+        .map(|c| {
+            serde_json::json!({
+                "id": c.id,
+                "shortDescription": { "text": format!("Found {}", c.found) },
+                "fullDescription": { "text": c.explanation },
+                "defaultConfiguration": { "level": c.severity.as_str() },
+            })
+        })
+        .collect();
+    let results: Vec<serde_json::Value> = findings
+        .iter()
+        .map(|f| {
+            serde_json::json!({
+                "ruleId": f.check.id,
+                "ruleIndex": CHECKS.iter().position(|c| c.id == f.check.id),
+                "level": f.check.severity.as_str(),
+                "message": { "text": format!("Found {}", f.check.found) },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": sarif_uri(&f.path) },
+                        "region": {
+                            "startLine": f.loc.lineno,
+                            "startColumn": f.loc.column,
+                            "snippet": { "text": scrub_ctrl(&f.text) },
+                        },
+                    },
+                }],
+            })
+        })
+        .collect();
+    let log = serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "mktool check-portability",
+                    "version": env!("MKTOOL_VERSION"),
+                    "informationUri": env!("CARGO_PKG_HOMEPAGE"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    });
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{log:#}")?;
+    stdout.flush()
+}
+
+/*
+ * A problem with an inline suppression.
+ */
+struct Notice {
+    path: PathBuf,
+    lineno: usize,
+    msg: String,
+}
+
+#[derive(Default)]
+struct ScanResult {
+    findings: Vec<Finding>,
+    notices: Vec<Notice>,
+}
+
+/*
+ * Add every line inside the command substitutions of a line, including any
+ * nested inside them, noting whether each is part of a feature probe.
+ */
+fn add_substs<'a>(
+    line: &'a LogicalLine,
+    in_probe: bool,
+    out: &mut Vec<Line<'a>>,
+) {
+    let in_function = out[0].in_function;
+    for sub in &line.substs {
+        let in_probe = in_probe || is_probe(&line.words, sub.within);
+        out.push(Line {
+            code: &sub.code,
+            code_locs: &sub.code_locs,
+            words: &sub.words,
+            in_function,
+            in_probe,
+        });
+        add_substs(sub, in_probe, out);
+    }
+}
+
+/*
+ * Run every check against a list of lines, adding any matches that are not
+ * suppressed by an inline comment to the results.
+ *
+ * Commands inside command substitutions are checked as part of the line
+ * that they appear on, so each check still matches a line at most once,
+ * at the first location found, but with edits that fix every match.
+ */
+fn check_lines(path: &Path, lines: &[LogicalLine], res: &mut ScanResult) {
+    let mut functions = Functions::default();
+    for lline in lines {
+        let in_function = functions.update(&lline.code);
+        let mut nested = vec![Line {
+            code: &lline.code,
+            code_locs: &lline.code_locs,
+            words: &lline.words,
+            in_function,
+            in_probe: false,
+        }];
+        add_substs(lline, false, &mut nested);
+        let mut ignore: Vec<(String, bool)> = lline
+            .comments
+            .iter()
+            .filter_map(|c| parse_inline(c))
+            .flatten()
+            .map(|id| (id, false))
+            .collect();
+        for check in CHECKS {
+            let mut matched = nested
+                .iter()
+                .filter_map(|line| (check.check)(line).map(|loc| (loc, line)));
+            let Some((loc, line)) = matched.next() else {
+                continue;
+            };
+            if let Some(i) = ignore.iter_mut().find(|(id, _)| id == check.id) {
+                i.1 = true;
                 continue;
             }
+            res.findings.push(Finding {
+                path: path.to_path_buf(),
+                lineno: lline.lineno,
+                loc,
+                text: lline.text.clone(),
+                check,
+                edits: check
+                    .fix
+                    .map(|fix| {
+                        let rest = matched.flat_map(|(_, l)| fix(l));
+                        fix(line).into_iter().chain(rest).collect()
+                    })
+                    .unwrap_or_default(),
+            });
+        }
+        for (id, _) in ignore.into_iter().filter(|(_, used)| !used) {
+            let msg = match find_check(&id) {
+                Some(_) => format!("unused suppression for {id}"),
+                None => format!("unknown check {id} in suppression"),
+            };
+            res.notices.push(Notice {
+                path: path.to_path_buf(),
+                lineno: lline.lineno,
+                msg,
+            });
         }
-
-        /*
-         * If we're still here then there's another $RANDOM on the line and
-         * we didn't already exit early for the acceptable cases.  Set exit
-         * status that will be used unless we exit early later.
-         */
-        rv = true;
     }
+}
 
-    rv
+/*
+ * Recursively list every regular file under a directory, reading each
+ * subdirectory in parallel.  As with the original WalkDir version, symlinks
+ * are not followed and any errors are silently ignored.
+ */
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut files = vec![];
+    let mut dirs = vec![];
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Ok(filetype) = entry.file_type() else {
+            continue;
+        };
+        if filetype.is_dir() {
+            dirs.push(entry.path());
+        } else if filetype.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.par_extend(dirs.par_iter().flat_map_iter(|d| walk(d)));
+    files
 }
 
-fn check_test_eq(line: &str) -> bool {
-    let words: Vec<_> = line.split_whitespace().collect();
-    let mut idx = 2;
-    while idx < words.len() {
-        if words[idx] == "=="
-            && (words[idx - 2] == "test" || words[idx - 2] == "[")
-        {
-            return true;
+/*
+ * Read a list of files, one per line, from a file or stdin if "-".
+ */
+fn read_input(input: &Path) -> io::Result<Vec<PathBuf>> {
+    let reader: Box<dyn BufRead> = match input.to_str() {
+        Some("-") => Box::new(io::stdin().lock()),
+        _ => Box::new(BufReader::new(fs::File::open(input)?)),
+    };
+    let mut files = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if !line.is_empty() {
+            files.push(PathBuf::from(line));
         }
-        idx += 1;
     }
-    false
+    Ok(files)
 }
 
-fn print_random_warning() {
-    let msg = r#"
-Explanation:
-===========================================================================
-The variable $RANDOM is not required for a POSIX-conforming shell, and
-many implementations of /bin/sh do not support it. It should therefore
-not be used in shell programs that are meant to be portable across a
-large number of POSIX-like systems.
-===========================================================================
-    "#;
-    println!("{msg}");
+/*
+ * Return every file to scan, either all files under the root directory or
+ * the files and directories listed relative to it.
+ */
+fn list_files(root: &Path, targets: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    if targets.is_empty() {
+        return Ok(walk(root));
+    }
+    let mut files = vec![];
+    for target in targets {
+        let path = root.join(target);
+        let md = fs::metadata(&path).map_err(|e| {
+            io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+        })?;
+        if md.is_dir() {
+            files.extend(walk(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
 }
 
-fn print_test_eq_error() {
-    let msg = r#"
-Explanation:
-===========================================================================
-The "test" command, as well as the "[" command, are not required to know
-the "==" operator. Only a few implementations like bash and some
-versions of ksh support it.
-
-When you run "test foo == foo" on a platform that does not support the
-"==" operator, the result will be "false" instead of "true". This can
-lead to unexpected behavior.
-
-There are two ways to fix this error message. If the file that contains
-the "test ==" is needed for building the package, you should create a
-patch for it, replacing the "==" operator with "=". If the file is not
-needed, add its name to the CHECK_PORTABILITY_SKIP variable in the
-package Makefile.
-===========================================================================
-    "#;
-    println!("{msg}");
+struct Scanner {
+    /* Directory that reported paths and globs are relative to. */
+    root: PathBuf,
+    experimental: bool,
+    skipglob: Vec<glob::Pattern>,
+    /* Files to check as shell scripts regardless of their hashbang. */
+    scripts: Vec<glob::Pattern>,
+    /* Map of patched files to the patch that modifies them. */
+    patched: HashMap<String, PathBuf>,
 }
 
-impl Cmd {
-    pub fn run(&self) -> Result<i32, Box<dyn std::error::Error>> {
-        let mut rv = 0;
+impl Scanner {
+    /*
+     * Check a single file, if it is one that we are interested in.
+     */
+    fn scan(&self, path: &Path) -> io::Result<ScanResult> {
+        let mut res = ScanResult::default();
 
         /*
-         * File globs to skip specified in CHECK_PORTABILITY_SKIP.
+         * Skip extensions we aren't interested in.
          */
-        let mut skipglob = vec![];
-        if let Ok(paths) = std::env::var("CHECK_PORTABILITY_SKIP") {
-            for p in paths.split_whitespace().collect::<Vec<&str>>() {
-                match glob::Pattern::new(p) {
-                    Ok(g) => skipglob.push(g),
-                    Err(e) => {
-                        eprintln!(
-                            "WARNING: invalid CHECK_PORTABILITY_SKIP glob '{p}': {e}"
-                        );
-                    }
-                }
-            }
+        let fname: &str =
+            &path.file_name().unwrap_or_default().to_string_lossy();
+        if SKIPEXT.iter().any(|ext| fname.ends_with(ext)) {
+            return Ok(res);
         }
 
         /*
-         * List of file extensions to skip.  These are plain strings rather
-         * than adding to skipglob as it's faster.  Based on the lists in
-         * check-portability.sh but with some additions.
+         * Make paths relative to the root directory as all
+         * CHECK_PORTABILITY_SKIP matches are relative to WRKSRC.
          */
-        const SKIPEXT: &[&str] = &[
-            "~",
-            ".1",
-            ".3",
-            ".C",
-            ".a",
-            ".ac",
-            ".c",
-            ".cc",
-            ".css",
-            ".cxx",
-            ".docbook",
-            ".dtd",
-            ".el",
-            ".f",
-            ".gif",
-            ".gn",
-            ".go",
-            ".gz",
-            ".h",
-            ".hpp",
-            ".htm",
-            ".html",
-            ".hxx",
-            ".idl",
-            ".inc",
-            ".jpg",
-            ".js",
-            ".json",
-            ".kicad_mod",
-            ".m4",
-            ".map",
-            ".md",
-            ".mo",
-            ".ogg",
-            ".orig",
-            ".page",
-            ".php",
-            ".pl",
-            ".png",
-            ".po",
-            ".properties",
-            ".py",
-            ".rb",
-            ".result",
-            ".svg",
-            ".test",
-            ".tfm",
-            ".ts",
-            ".txt",
-            ".vf",
-            ".xml",
-            ".xpm",
-        ];
+        let mpath = path.strip_prefix(&self.root).unwrap_or(path);
+        if self.skipglob.iter().any(|g| g.matches_path(mpath)) {
+            return Ok(res);
+        }
 
         /*
-         * Get list of patched files.
+         * If this file ends ".in" and we already have a patch for the
+         * generated file in the same directory then skip it, no need to
+         * patch both.
          */
-        let mut patched: HashSet<String> = HashSet::new();
-        if let Ok(patchdir) = std::env::var("PATCHDIR") {
-            for patch in
-                WalkDir::new(patchdir).into_iter().filter_map(|e| e.ok())
-            {
-                if !patch.file_type().is_file() {
-                    continue;
-                }
-                if !patch.file_name().to_string_lossy().starts_with("patch-") {
-                    continue;
-                }
-                let pfile = fs::File::open(patch.path())?;
-                let reader = BufReader::new(pfile);
-                for line in reader.lines() {
-                    let line = line?;
-                    if line.starts_with("+++") {
-                        let v: Vec<&str> =
-                            line.splitn(2, char::is_whitespace).collect();
-                        if v.len() == 2 {
-                            patched.insert(v[1].to_string());
-                        }
-                        break;
-                    }
-                }
+        let mstr = mpath.to_string_lossy();
+        if let Some(p) = mstr.strip_suffix(".in") {
+            if self.patched.contains_key(p) {
+                return Ok(res);
             }
         }
 
-        'nextfile: for entry in
-            WalkDir::new(".").into_iter().filter_map(|e| e.ok())
-        {
-            if !entry.file_type().is_file() {
-                continue;
-            }
-
-            /*
-             * Skip extensions we aren't interested in.
-             */
-            let fname: &str = &entry.file_name().to_string_lossy();
-            for ext in SKIPEXT {
-                if fname.ends_with(ext) {
-                    continue 'nextfile;
+        /*
+         * Makefiles are only checked in experimental mode, where the shell
+         * commands in each recipe are checked individually.
+         */
+        if self.experimental && is_makefile(fname) {
+            let contents =
+                String::from_utf8_lossy(&fs::read(path)?).into_owned();
+            for recipe in recipes(&contents) {
+                let mut lines = lex(&recipe.shell);
+                /*
+                 * Locations are mapped from the shell command back to the
+                 * Makefile text that it was extracted from.
+                 */
+                for line in &mut lines {
+                    line.lineno += recipe.lineno - 1;
+                    line.remap(&|loc| recipe.loc(loc));
+                    line.text.clone_from(&recipe.text);
+                }
+                if let Some(first) = lines.first_mut() {
+                    first.comments.splice(0..0, recipe.comments);
                 }
+                check_lines(mpath, &lines, &mut res);
             }
 
             /*
-             * If this filename ends ".in" and we already have a patch for the
-             * non-".in" filename then skip it, no need to patch both.
+             * Edits are relative to the recipe rather than the file, so
+             * Makefiles cannot currently be fixed.
              */
-            if let Some(p) =
-                entry.file_name().to_string_lossy().strip_suffix(".in")
-            {
-                if patched.contains(p) {
-                    continue 'nextfile;
-                }
+            for finding in &mut res.findings {
+                finding.edits.clear();
             }
+            return Ok(res);
+        }
 
-            let path = entry.path();
+        /*
+         * Only check files that will be run by sh, which in practice also
+         * avoids wasting time on binary and other non-shell files.
+         */
+        let file = fs::File::open(path)?;
+        let mut reader = BufReader::with_capacity(1024, file);
+        let head = reader.fill_buf()?;
+        let listed = self.scripts.iter().any(|g| g.matches_path(mpath));
+        let is_script = match hashbang(head) {
+            Hashbang::Sh => true,
+            Hashbang::Other => listed,
+            Hashbang::None => listed || is_sh_name(fname),
+        };
+        if !is_script {
+            return Ok(res);
+        }
 
-            /*
-             * Remove leading "./" from walkdir path entries as all
-             * CHECK_PORTABILITY_SKIP matches are relative to WRKDIR.
-             */
-            let mpath = match path.strip_prefix("./") {
-                Ok(p) => p,
-                Err(_) => path,
-            };
-            for g in &skipglob {
-                if g.matches_path(mpath) {
-                    continue 'nextfile;
+        /*
+         * Any invalid UTF-8 is simply replaced.
+         */
+        let mut contents = vec![];
+        reader.read_to_end(&mut contents)?;
+        let contents = String::from_utf8_lossy(&contents);
+        check_lines(mpath, &lex(&contents), &mut res);
+        Ok(res)
+    }
+}
+
+/*
+ * Parse a whitespace-separated list of globs from an environment variable.
+ */
+fn env_globs(var: &str, args: &[glob::Pattern]) -> Vec<glob::Pattern> {
+    let mut globs = args.to_vec();
+    if let Ok(paths) = std::env::var(var) {
+        for p in paths.split_whitespace() {
+            match glob::Pattern::new(p) {
+                Ok(g) => globs.push(g),
+                Err(e) => {
+                    eprintln!("WARNING: invalid {var} glob '{p}': {e}");
                 }
             }
+        }
+    }
+    globs
+}
 
-            /*
-             * Verify that the file starts with a shell hashbang, otherwise
-             * skip to avoid wasting time with non-shell files.
-             *
-             * XXX If CHECK_PORTABILITY_EXPERIMENTAL is enabled then we
-             * should continue to check Makefiles (see shell version),
-             * however that is not currently supported and may never be,
-             * given I don't know anyone who enables it.
-             */
-            let file = fs::File::open(path)?;
-            let mut reader = BufReader::with_capacity(1024, file);
-            let head = reader.fill_buf()?;
+/*
+ * Comment used for any newly created patch.
+ */
+const FIX_COMMENT: &str = "Use portable shell syntax.";
 
-            /*
-             * Perform the simple and fast hashbang check first.
-             */
-            if !head.starts_with(b"#!") {
-                continue 'nextfile;
+/*
+ * Apply the edits for a single file, creating or extending the patch for it,
+ * and return the path to the patch.
+ *
+ * If the file already has a patch then it is extended, otherwise a new patch
+ * is created along with a ".orig" copy of the file for mkpatches.
+ */
+fn fix_file(
+    root: &Path,
+    name: &str,
+    mut edits: Vec<Edit>,
+    patchdir: &Path,
+    patched: &HashMap<String, PathBuf>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = root.join(name);
+    let current = fs::read_to_string(&path)?;
+    let mut chars: Vec<char> = current.chars().collect();
+    edits.sort_by_key(|e| std::cmp::Reverse(e.pos));
+    edits.dedup();
+    for edit in &edits {
+        let end = edit.pos + edit.from.chars().count();
+        if !chars
+            .get(edit.pos..end)
+            .is_some_and(|s| s.iter().copied().eq(edit.from.chars()))
+        {
+            return Err("file has changed".into());
+        }
+        chars.splice(edit.pos..end, edit.to.chars());
+    }
+    let fixed: String = chars.into_iter().collect();
+
+    let (patchfile, mut patch) = match patched.get(name) {
+        Some(p) => (p.clone(), Patch::parse(&fs::read_to_string(p)?)),
+        None => {
+            let p = patchdir.join(patch_name(name));
+            if p.exists() {
+                return Err(format!("{} already exists", p.display()).into());
             }
+            (p, Patch::new(FIX_COMMENT, name))
+        }
+    };
+    let Some(file) = patch.file_mut(name) else {
+        return Err(
+            format!("{name} not found in {}", patchfile.display()).into()
+        );
+    };
+    if !file.update(&current, &fixed) {
+        return Err(format!(
+            "{} does not match current file",
+            patchfile.display()
+        )
+        .into());
+    }
 
-            /*
-             * More complicated check for "/bin/sh" somewhere on first line
-             * next.
-             */
-            let binsh = b"/bin/sh";
-            let Some(newline) = head.iter().position(|&c| c == b'\n') else {
-                continue 'nextfile;
-            };
-            let first = &head[..newline];
-            if !first.windows(binsh.len()).any(|win| win == binsh) {
-                continue 'nextfile;
-            }
+    if !patched.contains_key(name) {
+        let orig = root.join(format!("{name}.orig"));
+        if !orig.exists() {
+            fs::copy(&path, &orig)?;
+        }
+    }
+    fs::write(&patchfile, patch.to_string())?;
+    fs::write(&path, fixed)?;
+    Ok(patchfile)
+}
 
-            for (i, line) in reader.by_ref().lines().enumerate() {
-                /*
-                 * Silently skip any non-UTF-8 lines; the hashbang gate
-                 * already filters out binary files in practice.
-                 */
-                let Ok(line) = line else {
-                    continue;
-                };
-                /*
-                 * Remove all leading and trailing whitespace to simplify
-                 * matches, and ignore comments.
-                 */
-                let line = line.trim();
-                if line.starts_with('#') {
-                    continue;
-                }
-                if check_random(line) {
-                    eprintln!("WARNING: [check-portability] => Found $RANDOM:");
-                    eprintln!(
-                        "WARNING: [check-portability] {}:{}: {}",
-                        mpath.display(),
-                        i + 1,
-                        scrub_ctrl(line)
-                    );
-                    print_random_warning();
-                }
-                if check_test_eq(line) {
-                    eprintln!(
-                        "ERROR: [check-portability] => Found test ... == ...:"
-                    );
-                    eprintln!(
-                        "ERROR: [check-portability] {}:{}: {}",
-                        mpath.display(),
-                        i + 1,
-                        scrub_ctrl(line)
-                    );
-                    print_test_eq_error();
-                    rv = 1;
+/*
+ * Fix every file that has fixable matches, printing the path to each patch
+ * that is written.  The paths are printed to stderr if "stdout" is false, so
+ * that they do not get mixed up with machine-readable output.  Matches that
+ * were fixed are removed from the list, any that could not be are left to be
+ * reported as usual.
+ */
+fn fix(
+    findings: &mut Vec<Finding>,
+    root: &Path,
+    patchdir: &Path,
+    patched: &HashMap<String, PathBuf>,
+    stdout: bool,
+) {
+    let mut edits: BTreeMap<PathBuf, Vec<Edit>> = BTreeMap::new();
+    for f in findings.iter().filter(|f| !f.edits.is_empty()) {
+        edits.entry(f.path.clone()).or_default().extend(f.edits.clone());
+    }
+    let mut fixed = HashSet::new();
+    for (path, edits) in edits {
+        let name = path.to_string_lossy();
+        match fix_file(root, &name, edits, patchdir, patched) {
+            Ok(patchfile) => {
+                if stdout {
+                    println!("{}", patchfile.display());
+                } else {
+                    eprintln!("{}", patchfile.display());
                 }
+                fixed.insert(path);
             }
+            Err(e) => eprintln!(
+                "WARNING: [check-portability] unable to fix {}: {e}",
+                path.display()
+            ),
         }
-
-        Ok(rv)
     }
+    findings.retain(|f| f.edits.is_empty() || !fixed.contains(&f.path));
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Cmd {
+    pub fn run(&self) -> Result<i32, Box<dyn std::error::Error>> {
+        let mut rv = 0;
 
-    #[test]
-    fn test_random() {
-        assert!(check_random("$RANDOM"));
+        let mut suppressions = match &self.suppressions {
+            Some(path) => Some(Suppressions::open(path).map_err(|e| {
+                format!("unable to read {}: {e}", path.display())
+            })?),
+            None => None,
+        };
 
-        /*
-         * Only exact matches for prefix/suffix "$$" are valid.
-         */
-        assert!(check_random("-$RANDOM"));
-        assert!(check_random("$-$RANDOM"));
-        assert!(check_random("$RANDOM-"));
-        assert!(check_random("$RANDOM-$"));
-        assert!(!check_random("$$-$RANDOM"));
-        assert!(!check_random("$RANDOM-$$"));
+        let experimental = self.experimental
+            || std::env::var("CHECK_PORTABILITY_EXPERIMENTAL")
+                .is_ok_and(|v| v.eq_ignore_ascii_case("yes"));
 
         /*
-         * If we see GNU-style $$-$RANDOM anywhere then all other matches are
-         * effectively ignored.
+         * File globs to skip specified in CHECK_PORTABILITY_SKIP, and extra
+         * shell scripts to check in CHECK_PORTABILITY_SCRIPTS, in addition
+         * to any passed as arguments.
          */
-        assert!(!check_random("$RANDOM-$$ $RANDOM"));
-        assert!(!check_random("$RANDOM $RANDOM-$$"));
+        let skipglob = env_globs("CHECK_PORTABILITY_SKIP", &self.skip);
+        let scripts = env_globs("CHECK_PORTABILITY_SCRIPTS", &self.script);
 
         /*
-         * $RANDOM at the start of a variable name is fine, unless we also see
-         * a bare $RANDOM too (this differs from check-portability.awk which
-         * is first-match-wins).
+         * Get list of patched files.
          */
-        assert!(!check_random("$RANDOMIZE"));
-        assert!(!check_random("$RANDOM_ISH"));
-        assert!(check_random("$RANDOMIZE $RANDOM"));
+        let patchdir = self
+            .patchdir
+            .clone()
+            .or_else(|| std::env::var_os("PATCHDIR").map(PathBuf::from));
+        if self.fix && patchdir.is_none() {
+            return Err("-P or PATCHDIR must be set to use --fix".into());
+        }
+        let mut patched: HashMap<String, PathBuf> = HashMap::new();
+        if let Some(patchdir) = &patchdir {
+            for patch in
+                WalkDir::new(patchdir).into_iter().filter_map(|e| e.ok())
+            {
+                if !patch.file_type().is_file() {
+                    continue;
+                }
+                if !patch.file_name().to_string_lossy().starts_with("patch-") {
+                    continue;
+                }
+                let text = fs::read(patch.path())?;
+                for file in Patch::parse(&String::from_utf8_lossy(&text)).files
+                {
+                    patched.insert(file.path, patch.path().to_path_buf());
+                }
+            }
+        }
 
         /*
-         * Commented matches are fine.  Unfortunately we strip commented
-         * lines prior to calling check_random() currently, so this should
-         * go into an integration test.
+         * Walk and scan every file in parallel, then sort the results so that
+         * output is deterministic regardless of the order files were read.
          */
-        //assert_eq!(check_random("# $RANDOM"), false);
-        //assert_eq!(check_random("   # $RANDOM"), false);
+        let mut targets = self.files.clone();
+        if let Some(input) = &self.input {
+            targets.extend(read_input(input).map_err(|e| {
+                format!("unable to read {}: {e}", input.display())
+            })?);
+        }
+        let scanner = Scanner {
+            root: self.dir.clone(),
+            experimental,
+            skipglob,
+            scripts,
+            patched,
+        };
+        let pool = build_thread_pool(self.jobs)?;
+        let results: io::Result<Vec<ScanResult>> = pool.install(|| {
+            list_files(&self.dir, &targets)?
+                .par_iter()
+                .map(|path| scanner.scan(path))
+                .collect()
+        });
+        let mut findings = vec![];
+        let mut notices = vec![];
+        for res in results? {
+            findings.extend(res.findings);
+            notices.extend(res.notices);
+        }
+        findings
+            .sort_by(|a, b| a.path.cmp(&b.path).then(a.lineno.cmp(&b.lineno)));
+        notices
+            .sort_by(|a, b| a.path.cmp(&b.path).then(a.lineno.cmp(&b.lineno)));
+
+        if let Some(suppressions) = &mut suppressions {
+            findings.retain(|f| {
+                !suppressions.matches(&f.path, f.check.id, &f.text)
+            });
+        }
 
-        /*
-         * Misc non-matches.
-         */
-        assert!(!check_random(""));
-        assert!(!check_random("RANDOM"));
-        assert!(!check_random("$ RANDOM"));
-    }
+        if let Some(patchdir) = patchdir.as_deref().filter(|_| self.fix) {
+            fix(
+                &mut findings,
+                &self.dir,
+                patchdir,
+                &scanner.patched,
+                self.format == OutputFormat::Text,
+            );
+        }
 
-    #[test]
-    fn test_eq() {
-        assert!(check_test_eq("if [ foo == bar ]; then"));
+        if self.print_suppressions {
+            for f in &findings {
+                println!(
+                    "# {}:{}: {}",
+                    f.path.display(),
+                    f.lineno,
+                    scrub_ctrl(&f.text)
+                );
+                println!(
+                    "{}:{}:{}\tTODO",
+                    f.path.display(),
+                    f.check.id,
+                    line_hash(&f.text)
+                );
+            }
+            return Ok(0);
+        }
 
-        /* XXX: No support for whitespace in variable at present.  */
-        assert!(!check_test_eq("if [ 'foo bar' == ojnk ]; then"));
+        if findings.iter().any(|f| f.check.severity == Severity::Error) {
+            rv = 1;
+        }
+        match self.format {
+            OutputFormat::Text => findings.iter().for_each(Finding::print),
+            OutputFormat::Json => print_json(&findings)?,
+            OutputFormat::Sarif => print_sarif(&findings)?,
+        }
+        for notice in &notices {
+            eprintln!(
+                "WARNING: [check-portability] {}:{}: {}",
+                notice.path.display(),
+                notice.lineno,
+                notice.msg
+            );
+        }
+        if let Some(suppressions) = &suppressions {
+            suppressions.warn_unused();
+        }
 
-        /*
-         * Misc non-matches.
-         */
-        assert!(!check_test_eq(""));
-        assert!(!check_test_eq("foo == bar"));
-        assert!(!check_test_eq("if foo == bar"));
-        assert!(!check_test_eq("if [ foo = bar ]; then"));
+        Ok(rv)
     }
 }
@@ -0,0 +1,851 @@
+/*
+ * Copyright (c) 2024 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Individual portability checks.  Each check has a short identifier that is
+ * printed in the explanation for any match, so that it can be referred to
+ * (and ignored) individually.
+ *
+ * Only $RANDOM and "test ==" are implemented by the pkgsrc shell version of
+ * check-portability.  The remaining checks are all constructs that fail on
+ * at least one common /bin/sh (NetBSD sh, dash, Solaris /bin/sh), but they
+ * are only warnings so that they do not suddenly break existing packages.
+ */
+
+use super::lexer::{Loc, Word};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/*
+ * A single logical line to check, along with any context that the checks
+ * need.  See lexer.rs for the difference between code and words.
+ */
+#[derive(Debug, Default)]
+pub struct Line<'a> {
+    pub code: &'a str,
+    /* Location of each char in code. */
+    pub code_locs: &'a [Loc],
+    pub words: &'a [Word],
+    pub in_function: bool,
+    /*
+     * Whether this line is inside a command substitution whose output is
+     * only being tested, for example "case `echo -n x` in".
+     */
+    pub in_probe: bool,
+}
+
+impl Line<'_> {
+    /*
+     * Location of the char at a byte offset in code.
+     */
+    pub fn code_loc(&self, offset: usize) -> Loc {
+        let n = self.code[..offset].chars().count();
+        self.code_locs.get(n).copied().unwrap_or_default()
+    }
+}
+
+pub struct Check {
+    pub id: &'static str,
+    pub severity: Severity,
+    /* Printed as "Found <found>:" */
+    pub found: &'static str,
+    pub explanation: &'static str,
+    /* Location of the construct that matched, if any. */
+    pub check: fn(&Line) -> Option<Loc>,
+    /* Mechanical rewrite of a matching line, if there is one. */
+    pub fix: Option<fn(&Line) -> Vec<Edit>>,
+}
+
+/*
+ * Replace the text "from" at char offset "pos" in the source with "to".
+ */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Edit {
+    pub pos: usize,
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+pub const CHECKS: &[Check] = &[
+    Check {
+        id: "random",
+        severity: Severity::Warning,
+        found: "$RANDOM",
+        explanation: RANDOM,
+        check: |l| check_random(l.code).map(|n| l.code_loc(n)),
+        fix: None,
+    },
+    Check {
+        id: "test-eq",
+        severity: Severity::Error,
+        found: "test ... == ...",
+        explanation: TEST_EQ,
+        check: |l| check_test_eq(l.words).map(|w| w.loc),
+        fix: Some(|l| fix_test_eq(l.words)),
+    },
+    Check {
+        id: "double-bracket",
+        severity: Severity::Warning,
+        found: "[[ ... ]]",
+        explanation: DOUBLE_BRACKET,
+        check: |l| check_double_bracket(l.words).map(|w| w.loc),
+        fix: None,
+    },
+    Check {
+        id: "double-bracket-eq",
+        severity: Severity::Warning,
+        found: "[[ ... == ... ]]",
+        explanation: DOUBLE_BRACKET_EQ,
+        check: |l| check_double_bracket_eq(l.words).map(|w| w.loc),
+        fix: None,
+    },
+    Check {
+        id: "function-keyword",
+        severity: Severity::Warning,
+        found: "function name",
+        explanation: FUNCTION_KEYWORD,
+        check: |l| check_function_keyword(l.words).map(|w| w.loc),
+        fix: None,
+    },
+    Check {
+        id: "source",
+        severity: Severity::Warning,
+        found: "source",
+        explanation: SOURCE,
+        check: |l| check_source(l.words).map(|w| w.loc),
+        fix: None,
+    },
+    Check {
+        id: "echo-flags",
+        severity: Severity::Warning,
+        found: "echo -e/-n",
+        explanation: ECHO_FLAGS,
+        check: |l| {
+            check_echo_flags(l.words).filter(|_| !l.in_probe).map(|w| w.loc)
+        },
+        fix: None,
+    },
+    Check {
+        id: "pattern-substitution",
+        severity: Severity::Warning,
+        found: "${var/pattern/replacement}",
+        explanation: PATTERN_SUBSTITUTION,
+        check: |l| check_pattern_substitution(l.code).map(|n| l.code_loc(n)),
+        fix: None,
+    },
+    Check {
+        id: "substring",
+        severity: Severity::Warning,
+        found: "${var:offset:length}",
+        explanation: SUBSTRING,
+        check: |l| check_substring(l.code).map(|n| l.code_loc(n)),
+        fix: None,
+    },
+    Check {
+        id: "dollar-single-quote",
+        severity: Severity::Warning,
+        found: "$'...'",
+        explanation: DOLLAR_SINGLE_QUOTE,
+        check: |l| check_dollar_single_quote(l.words).map(|w| w.loc),
+        fix: None,
+    },
+    Check {
+        id: "redirect-all",
+        severity: Severity::Warning,
+        found: "&>",
+        explanation: REDIRECT_ALL,
+        check: |l| check_redirect_all(l.words).map(|w| w.loc),
+        fix: None,
+    },
+    Check {
+        id: "array",
+        severity: Severity::Warning,
+        found: "array",
+        explanation: ARRAY,
+        check: |l| check_array(l),
+        fix: None,
+    },
+    Check {
+        id: "local-outside-function",
+        severity: Severity::Warning,
+        found: "local outside of a function",
+        explanation: LOCAL_OUTSIDE_FUNCTION,
+        check: |l| {
+            check_local(l.words).filter(|_| !l.in_function).map(|w| w.loc)
+        },
+        fix: None,
+    },
+];
+
+/*
+ * Look up a check by its identifier.
+ */
+pub fn find_check(id: &str) -> Option<&'static Check> {
+    CHECKS.iter().find(|c| c.id == id)
+}
+
+const RULE: &str = "===========================================================================";
+
+/*
+ * The "=> Found" line printed before this must stay compatible with the
+ * pkgsrc version, so the check id is only shown here.
+ */
+pub fn print_explanation(check: &Check) {
+    println!(
+        "\nExplanation:\n{RULE}\n{}\nThis is check \"{}\", which may be ignored using a\n\
+         \"# mktool: ignore {}\" comment.\n{RULE}\n    ",
+        check.explanation, check.id, check.id
+    );
+}
+
+/*
+ * Split a line into simple commands, each a list of words, skipping any
+ * leading reserved words so that the first word is the command name.
+ */
+fn commands(words: &[Word]) -> Vec<Vec<&Word>> {
+    let mut cmds = vec![];
+    let mut cmd: Vec<&Word> = vec![];
+    for word in words {
+        /*
+         * Any operator other than a redirection separates commands.
+         */
+        if word.op && !word.text.contains(['<', '>']) {
+            cmds.push(std::mem::take(&mut cmd));
+            continue;
+        }
+        if cmd.is_empty()
+            && ["!", "{", "do", "elif", "else", "if", "then", "until", "while"]
+                .iter()
+                .any(|r| word.is(r))
+        {
+            continue;
+        }
+        cmd.push(word);
+    }
+    cmds.push(cmd);
+    cmds.retain(|c| !c.is_empty());
+    cmds
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/*
+ * Return the offset of every "${name" on the line, along with what follows
+ * the parameter name.
+ */
+fn param_ops(line: &str) -> Vec<(usize, &str)> {
+    let mut ops = vec![];
+    for (start, _) in line.match_indices("${") {
+        let rest = &line[start + 2..];
+        let len = match rest.as_bytes().first() {
+            Some(b'@' | b'*') => 1,
+            _ => rest.bytes().take_while(|&b| is_name_byte(b)).count(),
+        };
+        if len > 0 {
+            ops.push((start, &rest[len..]));
+        }
+    }
+    ops
+}
+
+/*
+ * Return the offset of the first unacceptable $RANDOM on the line.
+ */
+pub fn check_random(line: &str) -> Option<usize> {
+    let mut rv = None;
+    let bytes = line.as_bytes();
+    for (start, _) in line.match_indices("$RANDOM") {
+        let next = start + "$RANDOM".len();
+
+        /*
+         * $RANDOM mixed with PID ($$) is commonly found in GNU configure
+         * scripts, and because they are always executed using a compatible
+         * shell then are considered acceptable.  Turning this off produces
+         * lots of false positives in e.g. config.guess.
+         */
+        if start >= 3 && &bytes[start - 3..start] == b"$$-" {
+            return None;
+        }
+        if bytes.get(next..next + 3) == Some(b"-$$") {
+            return None;
+        }
+
+        /*
+         * Trailing A-Z_, i.e. a variable that starts "$RANDOM.." such as
+         * $RANDOMIZE is considered acceptable, but only if there is no bare
+         * $RANDOM elsewhere on the line, so continue to other matches.
+         */
+        if let Some(&b) = bytes.get(next) {
+            if b.is_ascii_uppercase() || b == b'_' {
+                continue;
+            }
+        }
+
+        /*
+         * If we're still here then there's another $RANDOM on the line and
+         * we didn't already exit early for the acceptable cases.  Set exit
+         * status that will be used unless we exit early later.
+         */
+        rv = rv.or(Some(start));
+    }
+
+    rv
+}
+
+/*
+ * Return every "==" argument to test or [.
+ */
+fn test_eq_words(words: &[Word]) -> Vec<&Word> {
+    commands(words)
+        .into_iter()
+        .filter(|c| c[0].is("test") || c[0].is("["))
+        .flat_map(|c| c.into_iter().skip(2).filter(|w| w.is("==")))
+        .collect()
+}
+
+pub fn check_test_eq(words: &[Word]) -> Option<&Word> {
+    test_eq_words(words).into_iter().next()
+}
+
+fn fix_test_eq(words: &[Word]) -> Vec<Edit> {
+    test_eq_words(words)
+        .into_iter()
+        .map(|w| Edit { pos: w.pos, from: "==", to: "=" })
+        .collect()
+}
+
+fn check_double_bracket(words: &[Word]) -> Option<&Word> {
+    commands(words).into_iter().map(|c| c[0]).find(|w| w.is("[["))
+}
+
+fn check_double_bracket_eq(words: &[Word]) -> Option<&Word> {
+    commands(words)
+        .into_iter()
+        .filter(|c| c[0].is("[["))
+        .find_map(|c| c.into_iter().find(|w| w.is("==")))
+}
+
+fn check_function_keyword(words: &[Word]) -> Option<&Word> {
+    commands(words)
+        .into_iter()
+        .find(|c| c[0].is("function") && c.len() > 1)
+        .map(|c| c[0])
+}
+
+/*
+ * Whether the word at pos is the subject of a case statement or an argument
+ * to test.  A command substitution there is probing for how the shell
+ * behaves, as configure scripts do to choose between "echo -n" and "\c",
+ * rather than relying on any one behaviour.
+ */
+pub fn is_probe(words: &[Word], pos: usize) -> bool {
+    commands(words).into_iter().any(|c| {
+        if c[0].is("case") {
+            c.get(1).is_some_and(|w| w.pos == pos)
+        } else if c[0].is("test") || c[0].is("[") {
+            c[1..].iter().any(|w| w.pos == pos)
+        } else {
+            false
+        }
+    })
+}
+
+fn check_source(words: &[Word]) -> Option<&Word> {
+    commands(words)
+        .into_iter()
+        .find(|c| c[0].is("source") && c.len() > 1)
+        .map(|c| c[0])
+}
+
+/*
+ * Return the flag argument, rather than the echo itself.
+ */
+fn check_echo_flags(words: &[Word]) -> Option<&Word> {
+    commands(words).into_iter().find_map(|c| {
+        c.get(1).copied().filter(|w| {
+            c[0].is("echo")
+                && !w.op
+                && w.text.len() > 1
+                && w.text.starts_with('-')
+                && w.text[1..].chars().all(|ch| matches!(ch, 'e' | 'n' | 'E'))
+        })
+    })
+}
+
+fn check_pattern_substitution(code: &str) -> Option<usize> {
+    param_ops(code)
+        .into_iter()
+        .find(|(_, op)| op.starts_with('/'))
+        .map(|(n, _)| n)
+}
+
+fn check_substring(code: &str) -> Option<usize> {
+    param_ops(code)
+        .into_iter()
+        .find(|(_, op)| {
+            op.strip_prefix(':').is_some_and(|o| {
+                !o.is_empty() && !o.starts_with(['-', '=', '?', '+', '}'])
+            })
+        })
+        .map(|(n, _)| n)
+}
+
+fn check_dollar_single_quote(words: &[Word]) -> Option<&Word> {
+    words.iter().find(|w| w.ansi_c)
+}
+
+/*
+ * "&>file" and "&>>file", but not the POSIX ">&" or "<&" redirections.
+ */
+fn check_redirect_all(words: &[Word]) -> Option<&Word> {
+    words.iter().find(|w| w.is_op("&>") || w.is_op("&>>"))
+}
+
+/*
+ * Array assignments "name=(...)" or "name+=(...)", element references
+ * "${name[...]}", and "declare -a" or "declare -A".
+ */
+fn check_array(line: &Line) -> Option<Loc> {
+    for cmd in commands(line.words) {
+        if cmd[0].is("declare")
+            && cmd[1..].iter().any(|w| {
+                w.text.starts_with('-') && w.text[1..].contains(['a', 'A'])
+            })
+        {
+            return Some(cmd[0].loc);
+        }
+        for word in &cmd {
+            if word.op {
+                continue;
+            }
+            let Some((name, _)) = word.raw.split_once("=(") else {
+                continue;
+            };
+            let name = name.strip_suffix('+').unwrap_or(name);
+            if !name.is_empty()
+                && name.bytes().all(is_name_byte)
+                && !name.as_bytes()[0].is_ascii_digit()
+            {
+                return Some(word.loc);
+            }
+        }
+    }
+    param_ops(line.code)
+        .into_iter()
+        .find(|(_, op)| op.starts_with('['))
+        .map(|(n, _)| line.code_loc(n))
+}
+
+fn check_local(words: &[Word]) -> Option<&Word> {
+    commands(words).into_iter().map(|c| c[0]).find(|w| w.is("local"))
+}
+
+/*
+ * Track whether we are inside a shell function across lines.  This is a
+ * simple heuristic based on function headers and brace words, and does not
+ * handle functions whose body is a subshell.
+ */
+#[derive(Debug, Default)]
+pub struct Functions {
+    /* Brace depth at each function header, and whether its body opened. */
+    stack: Vec<(usize, bool)>,
+    depth: usize,
+}
+
+/*
+ * If this line starts a function definition, return the remainder of the
+ * line following the header.
+ */
+fn function_header(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if let Some(rest) = line.strip_prefix("function ") {
+        let rest = rest.trim_start();
+        let len =
+            rest.bytes().take_while(|&b| is_name_byte(b) || b == b'-').count();
+        if len == 0 {
+            return None;
+        }
+        let rest = rest[len..].trim_start();
+        return Some(rest.strip_prefix("()").unwrap_or(rest));
+    }
+    let (name, rest) = line.split_once('(')?;
+    let name = name.trim_end();
+    let rest = rest.trim_start().strip_prefix(')')?;
+    if name.is_empty()
+        || !name.bytes().all(|b| is_name_byte(b) || b == b'-' || b == b'.')
+    {
+        return None;
+    }
+    Some(rest)
+}
+
+impl Functions {
+    /*
+     * Update state for a new line, returning whether the line is inside a
+     * function (including its header).
+     */
+    pub fn update(&mut self, line: &str) -> bool {
+        let mut words = line;
+        if let Some(rest) = function_header(line) {
+            self.stack.push((self.depth, false));
+            words = rest;
+        }
+        let in_function = !self.stack.is_empty();
+
+        for word in words.split_whitespace() {
+            if word.starts_with('{') {
+                self.depth += 1;
+                if let Some(f) = self.stack.last_mut() {
+                    if f.0 < self.depth {
+                        f.1 = true;
+                    }
+                }
+            } else if word.starts_with('}') {
+                self.depth = self.depth.saturating_sub(1);
+                while let Some(&(start, true)) = self.stack.last() {
+                    if self.depth > start {
+                        break;
+                    }
+                    self.stack.pop();
+                }
+            }
+        }
+
+        in_function
+    }
+}
+
+const RANDOM: &str = r#"The variable $RANDOM is not required for a POSIX-conforming shell, and
+many implementations of /bin/sh do not support it. It should therefore
+not be used in shell programs that are meant to be portable across a
+large number of POSIX-like systems.
+"#;
+
+const TEST_EQ: &str = r#"The "test" command, as well as the "[" command, are not required to know
+the "==" operator. Only a few implementations like bash and some
+versions of ksh support it.
+
+When you run "test foo == foo" on a platform that does not support the
+"==" operator, the result will be "false" instead of "true". This can
+lead to unexpected behavior.
+
+There are two ways to fix this error message. If the file that contains
+the "test ==" is needed for building the package, you should create a
+patch for it, replacing the "==" operator with "=". If the file is not
+needed, add its name to the CHECK_PORTABILITY_SKIP variable in the
+package Makefile.
+"#;
+
+const DOUBLE_BRACKET: &str = r#"The "[[" command is a bash and ksh extension. A POSIX /bin/sh will
+fail with "[[: not found", and the condition will be treated as false.
+
+Replace it with "[" or "test", splitting any "&&" or "||" inside the
+brackets into separate tests, and quoting any variables.
+"#;
+
+const DOUBLE_BRACKET_EQ: &str = r#"The "==" operator is being used inside "[[". In addition to "[[" itself
+not being portable, converting this to "[" requires "==" to be replaced
+with "=", otherwise the test will silently fail on shells that do not
+support it.
+"#;
+
+const FUNCTION_KEYWORD: &str = r#"The "function" keyword is not part of POSIX, and /bin/sh on many
+systems will fail with a syntax error.
+
+Define functions using the portable "name() { ...; }" form instead.
+"#;
+
+const SOURCE: &str = r#"The "source" command is a bash and csh alias for ".", and does not
+exist in a POSIX /bin/sh.
+
+Use "." instead. Note that "." searches PATH for files that do not
+contain a slash, so use "./file" for files in the current directory.
+"#;
+
+const ECHO_FLAGS: &str = r#"POSIX does not define any options for "echo", and implementations differ
+on whether "-e" and "-n" are options or text to print. Escape sequences
+in arguments are also handled differently between shells.
+
+Use "printf" instead, e.g. "printf '%s'" in place of "echo -n", and
+"printf '%b\n'" where escape sequences need to be interpreted.
+"#;
+
+const PATTERN_SUBSTITUTION: &str = r###"The "${var/pattern/replacement}" expansion is a bash and ksh extension,
+and is a syntax error in a POSIX /bin/sh.
+
+Use an external command such as sed instead, or the "%", "%%", "#" and
+"##" expansions if only a prefix or suffix needs to be removed.
+"###;
+
+const SUBSTRING: &str = r##"The "${var:offset:length}" substring expansion is a bash and ksh
+extension, and is a syntax error in a POSIX /bin/sh.
+
+Use an external command such as cut, expr or sed instead, or the "%"
+and "#" expansions where possible.
+"##;
+
+const DOLLAR_SINGLE_QUOTE: &str = r#"The $'...' form of quoting, where backslash escapes are interpreted, is
+not supported by many implementations of /bin/sh. Those that do not
+support it will instead output a literal "$" followed by the string.
+
+Use printf to generate strings that contain special characters.
+"#;
+
+const REDIRECT_ALL: &str = r#"The "&>file" redirection is a bash extension. A POSIX /bin/sh instead
+runs the preceding command in the background and then truncates "file",
+so output is not redirected as intended.
+
+Use ">file 2>&1" instead.
+"#;
+
+const ARRAY: &str = r#"Arrays are not supported by a POSIX /bin/sh, and their use will result
+in syntax errors or unexpected behaviour.
+
+Use the positional parameters with "set --" where a single list is
+required, or otherwise restructure the script to use plain variables.
+"#;
+
+const LOCAL_OUTSIDE_FUNCTION: &str = r#"The "local" command is being used outside of a function. While "local"
+is widely supported inside functions, every shell that implements it
+considers this an error.
+
+Remove the "local" keyword, or move the code into a function.
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::super::lexer::lex;
+    use super::*;
+
+    #[test]
+    fn test_random() {
+        assert!(check_random("$RANDOM").is_some());
+
+        /*
+         * Only exact matches for prefix/suffix "$$" are valid.
+         */
+        assert!(check_random("-$RANDOM").is_some());
+        assert!(check_random("$-$RANDOM").is_some());
+        assert!(check_random("$RANDOM-").is_some());
+        assert!(check_random("$RANDOM-$").is_some());
+        assert_eq!(check_random("$$-$RANDOM"), None);
+        assert_eq!(check_random("$RANDOM-$$"), None);
+
+        /*
+         * If we see GNU-style $$-$RANDOM anywhere then all other matches are
+         * effectively ignored.
+         */
+        assert_eq!(check_random("$RANDOM-$$ $RANDOM"), None);
+        assert_eq!(check_random("$RANDOM $RANDOM-$$"), None);
+
+        /*
+         * $RANDOM at the start of a variable name is fine, unless we also see
+         * a bare $RANDOM too (this differs from check-portability.awk which
+         * is first-match-wins).
+         */
+        assert_eq!(check_random("$RANDOMIZE"), None);
+        assert_eq!(check_random("$RANDOM_ISH"), None);
+        assert!(check_random("$RANDOMIZE $RANDOM").is_some());
+
+        /*
+         * Commented and quoted matches are removed by the lexer before
+         * check_random() is called, see test_bashisms().
+         */
+
+        /*
+         * Misc non-matches.
+         */
+        assert_eq!(check_random(""), None);
+        assert_eq!(check_random("RANDOM"), None);
+        assert_eq!(check_random("$ RANDOM"), None);
+    }
+
+    fn has_test_eq(text: &str) -> bool {
+        lex(text).iter().any(|l| check_test_eq(&l.words).is_some())
+    }
+
+    #[test]
+    fn test_eq() {
+        assert!(has_test_eq("if [ foo == bar ]; then"));
+        assert!(has_test_eq("if [ 'foo bar' == ojnk ]; then"));
+        assert!(has_test_eq("if test \\\n  \"$a\" \\\n  == b; then"));
+        assert!(has_test_eq("[ -n \"$a\" -a \"$a\" == b ] && :"));
+
+        /*
+         * Misc non-matches.
+         */
+        assert!(!has_test_eq(""));
+        assert!(!has_test_eq("foo == bar"));
+        assert!(!has_test_eq("if foo == bar"));
+        assert!(!has_test_eq("if [ foo = bar ]; then"));
+        assert!(!has_test_eq("echo '[ foo == bar ]'"));
+        assert!(!has_test_eq("echo [ foo == bar ] # [ a == b ]"));
+        assert!(!has_test_eq("[ \"$a\" = \"==\" ]"));
+        assert!(!has_test_eq("cat <<EOF\n[ a == b ]\nEOF"));
+
+        /*
+         * Only the "==" arguments to test are rewritten.
+         */
+        let lines = lex("[ a == b ] && test c == d; echo ==");
+        let edits = fix_test_eq(&lines[0].words);
+        assert_eq!(edits.iter().map(|e| e.pos).collect::<Vec<_>>(), [4, 21]);
+    }
+
+    /*
+     * Run every check against a line, returning the ids that matched.
+     */
+    fn matches(text: &str, in_function: bool) -> Vec<&'static str> {
+        let mut ids = vec![];
+        for l in lex(text) {
+            let line = Line {
+                code: &l.code,
+                code_locs: &l.code_locs,
+                words: &l.words,
+                in_function,
+                in_probe: false,
+            };
+            ids.extend(
+                CHECKS
+                    .iter()
+                    .filter(|c| (c.check)(&line).is_some())
+                    .map(|c| c.id),
+            );
+        }
+        ids
+    }
+
+    /*
+     * Checks return the location of the construct that matched, rather than
+     * the start of the line.
+     */
+    #[test]
+    fn test_locations() {
+        let src = "x=1; if [ a == b ]; then\n  echo -n \\\n   ${x:1:2} $RANDOM &>/dev/null\n";
+        let lines = lex(src);
+        let loc = |id, n: usize| {
+            let l = &lines[n];
+            let line = Line {
+                code: &l.code,
+                code_locs: &l.code_locs,
+                words: &l.words,
+                in_function: false,
+                in_probe: false,
+            };
+            find_check(id).and_then(|c| (c.check)(&line))
+        };
+        let at = |lineno, column| Some(Loc { lineno, column });
+        assert_eq!(loc("test-eq", 0), at(1, 13));
+        assert_eq!(loc("echo-flags", 1), at(2, 8));
+        assert_eq!(loc("substring", 1), at(3, 4));
+        assert_eq!(loc("random", 1), at(3, 13));
+        assert_eq!(loc("redirect-all", 1), at(3, 21));
+        assert_eq!(loc("source", 1), None);
+    }
+
+    #[test]
+    fn test_bashisms() {
+        for (text, ids) in [
+            ("if [[ -n $foo ]]; then", vec!["double-bracket"]),
+            ("[[ $a == b ]] && x", vec!["double-bracket", "double-bracket-eq"]),
+            ("function foo {", vec!["function-keyword"]),
+            ("source ./env.sh", vec!["source"]),
+            ("foo && source bar", vec!["source"]),
+            ("echo -n foo", vec!["echo-flags"]),
+            ("echo -e 'a\\tb'", vec!["echo-flags"]),
+            ("x=${foo/bar/baz}", vec!["pattern-substitution"]),
+            ("x=\"${@//a/b}\"", vec!["pattern-substitution"]),
+            ("x=${foo:1:2}", vec!["substring"]),
+            ("x=${foo: -1}", vec!["substring"]),
+            ("IFS=$'\\n'", vec!["dollar-single-quote"]),
+            ("make &>/dev/null", vec!["redirect-all"]),
+            ("make &>>log", vec!["redirect-all"]),
+            ("arr=(a b c)", vec!["array"]),
+            ("arr+=(d)", vec!["array"]),
+            ("echo ${arr[0]}", vec!["array"]),
+            ("declare -a arr", vec!["array"]),
+            ("local foo=bar", vec!["local-outside-function"]),
+        ] {
+            assert_eq!(matches(text, false), ids, "{text}");
+        }
+
+        /*
+         * Portable constructs that look similar.
+         */
+        for text in [
+            "if [ -n \"$foo\" ]; then",
+            "foo() {",
+            ". ./env.sh",
+            "echo foo -n",
+            "echo -",
+            "x=${foo%/*} y=${foo##*/}",
+            "x=${foo:-bar} ${foo:=bar} ${foo:?bar} ${foo:+bar}",
+            "grep 'foo$' bar",
+            "echo \"$'\"",
+            "make >/dev/null 2>&1",
+            "exec 3>&-",
+            "set -- a b c",
+            "x=$(foo) y=`bar`",
+            "echo ${#foo}",
+            "   # $RANDOM [[",
+            "echo foo # source bar",
+            "echo '$RANDOM ${a/b/c}' \\$RANDOM",
+            "echo 'source foo'; x='[['",
+            "printf '%s\\n' \"a &> b\"",
+            "cat <<EOF\n[[ $RANDOM ]]\nsource foo\nEOF",
+        ] {
+            assert_eq!(matches(text, false), Vec::<&str>::new(), "{text}");
+        }
+
+        assert!(matches("local foo", true).is_empty());
+    }
+
+    #[test]
+    fn test_functions() {
+        let mut f = Functions::default();
+        let script = [
+            ("local a", false),
+            ("foo() {", true),
+            ("  if true; then { echo; }; fi", true),
+            ("  local b", true),
+            ("}", true),
+            ("local c", false),
+            ("function bar", true),
+            ("{", true),
+            ("  local d", true),
+            ("}", true),
+            ("baz() { local e; }", true),
+            ("local f", false),
+        ];
+        for (line, expected) in script {
+            assert_eq!(f.update(line), expected, "{line}");
+        }
+    }
+}
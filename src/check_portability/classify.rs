@@ -0,0 +1,118 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Decide whether a file is a script that will be run by a POSIX /bin/sh.
+ *
+ * A hashbang is authoritative, so a "foo.sh" that starts "#!/bin/bash" is
+ * not checked.  Files without a hashbang are checked if they have a name
+ * that is conventionally run by sh, for example "configure" scripts that
+ * are run with ${CONFIG_SHELL}.
+ */
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Hashbang {
+    /* No hashbang. */
+    None,
+    /* A hashbang for sh. */
+    Sh,
+    /* A hashbang for any other interpreter. */
+    Other,
+}
+
+/*
+ * Autoconf substitutions that are commonly used for the path to sh in the
+ * hashbang of ".in" files.
+ */
+const SH_SUBST: &[&str] =
+    &["@SHELL@", "@SH@", "@CONFIG_SHELL@", "@POSIX_SHELL@"];
+
+fn is_sh(interp: &str) -> bool {
+    interp.rsplit('/').next() == Some("sh") || SH_SUBST.contains(&interp)
+}
+
+/*
+ * Classify the hashbang (if any) at the start of a file.  Any amount of
+ * whitespace is accepted around the interpreter, and "env" is followed to
+ * the command it runs, skipping any options or variable assignments.
+ */
+pub fn hashbang(head: &[u8]) -> Hashbang {
+    let Some(rest) = head.strip_prefix(b"#!") else {
+        return Hashbang::None;
+    };
+    let line = rest.split(|&c| c == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let mut words = line.split_whitespace();
+    let mut interp = words.next().unwrap_or_default();
+    if interp.rsplit('/').next() == Some("env") {
+        interp = words
+            .find(|w| !w.starts_with('-') && !w.contains('='))
+            .unwrap_or_default();
+    }
+    if is_sh(interp) { Hashbang::Sh } else { Hashbang::Other }
+}
+
+/*
+ * Whether a file without a hashbang should be treated as a shell script
+ * based on its name.
+ */
+pub fn is_sh_name(fname: &str) -> bool {
+    fname == "configure"
+        || fname.strip_suffix(".in").unwrap_or(fname).ends_with(".sh")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashbang() {
+        for h in [
+            "#!/bin/sh\n",
+            "#! /bin/sh -e\n",
+            "#!\t/bin/sh\t-x\r\n",
+            "#!/usr/bin/sh",
+            "#!/usr/xpg4/bin/sh\n",
+            "#!/usr/bin/env sh\n",
+            "#! /usr/bin/env -i PATH=/bin sh -e\n",
+            "#!@SHELL@\n",
+        ] {
+            assert_eq!(hashbang(h.as_bytes()), Hashbang::Sh, "{h}");
+        }
+        for h in [
+            "#!/bin/bash\n",
+            "#!/bin/shx\n",
+            "#!/usr/bin/env bash\n",
+            "#!/usr/bin/env\n",
+            "#!\n",
+            "#!/usr/bin/perl\n# /bin/sh\n",
+        ] {
+            assert_eq!(hashbang(h.as_bytes()), Hashbang::Other, "{h}");
+        }
+        assert_eq!(hashbang(b""), Hashbang::None);
+        assert_eq!(hashbang(b"# /bin/sh\n"), Hashbang::None);
+    }
+
+    #[test]
+    fn test_is_sh_name() {
+        for f in ["configure", "build.sh", "build.sh.in"] {
+            assert!(is_sh_name(f), "{f}");
+        }
+        for f in ["configure.in", "configure.ac", "sh", "build.bash"] {
+            assert!(!is_sh_name(f), "{f}");
+        }
+    }
+}
@@ -0,0 +1,723 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * A lightweight shell lexer, just enough to split a script into lines of
+ * words and operators for the portability checks.
+ *
+ * This understands single and double quotes, backslash escapes, $'...',
+ * ${...}, $(...) and backquotes, comments, line continuations and
+ * here-documents.  It does not attempt to parse the shell grammar, and
+ * anything it doesn't understand is passed through as part of a word.
+ *
+ * Each logical line is returned with its words, and a "code" version of the
+ * line with comments removed and the contents of single quotes and escaped
+ * characters blanked out, for checks that search for expansions.  Heredoc
+ * bodies are skipped entirely.
+ *
+ * The bodies of $(...) and backquote command substitutions are kept as part
+ * of the word they appear in, but are also lexed separately into their own
+ * lines so that the commands inside them can be checked.
+ *
+ * Comments are recorded against the line they appear on.  Comments on lines
+ * by themselves are instead attached to the next logical line, unless
+ * separated from it by a blank line.
+ */
+
+/*
+ * Line and column in the source, both starting at 1.
+ */
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Loc {
+    pub lineno: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Word {
+    /* Original text, including any quotes. */
+    pub raw: String,
+    /* Text with quotes and escapes removed. */
+    pub text: String,
+    /* Whether any part of the word was quoted or escaped. */
+    pub quoted: bool,
+    /* An operator such as ";", "&&" or ">>" rather than a word. */
+    pub op: bool,
+    /* Contains $'...' quoting. */
+    pub ansi_c: bool,
+    /* Offset of the start of the word in the source, in chars. */
+    pub pos: usize,
+    /* Location of the start of the word. */
+    pub loc: Loc,
+}
+
+impl Word {
+    /*
+     * An unquoted word with this exact text, i.e. a reserved word or
+     * command name.
+     */
+    pub fn is(&self, s: &str) -> bool {
+        !self.op && !self.quoted && self.text == s
+    }
+
+    pub fn is_op(&self, s: &str) -> bool {
+        self.op && self.text == s
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LogicalLine {
+    /* Line number that this logical line starts on, starting at 1. */
+    pub lineno: usize,
+    /* Source text, with continued lines joined, for display. */
+    pub text: String,
+    pub code: String,
+    /* Location of each char in code. */
+    pub code_locs: Vec<Loc>,
+    pub words: Vec<Word>,
+    /* Comment text, including the leading "#". */
+    pub comments: Vec<String>,
+    /* Lines inside any command substitutions. */
+    pub substs: Vec<LogicalLine>,
+    /*
+     * For lines inside a command substitution, the offset of the word in
+     * the enclosing line that contains it.
+     */
+    pub within: usize,
+}
+
+/*
+ * Operators, longest first so that the first match wins.
+ */
+const OPERATORS: &[&str] = &[
+    "&>>", "<<-", ";;", "&&", "||", "&>", ">>", ">&", ">|", "<<", "<&", "<>",
+    ";", "&", "|", "<", ">", "(", ")",
+];
+
+impl LogicalLine {
+    /*
+     * Map every location to a new one, for lines that have been lexed from
+     * text that was extracted from the file they are in.
+     */
+    pub fn remap(&mut self, map: &dyn Fn(Loc) -> Loc) {
+        let words = self.words.iter_mut().map(|w| &mut w.loc);
+        for loc in words.chain(self.code_locs.iter_mut()) {
+            *loc = map(*loc);
+        }
+        for line in &mut self.substs {
+            line.remap(map);
+        }
+    }
+}
+
+/*
+ * Code for a logical line, along with the location of each char.
+ */
+#[derive(Default)]
+struct Code {
+    text: String,
+    locs: Vec<Loc>,
+}
+
+/*
+ * Command substitutions are lexed using a lexer over the same source,
+ * ending where the substitution does, so that all offsets are always from
+ * the start of the source.
+ */
+struct Lexer<'a> {
+    chars: &'a [char],
+    pos: usize,
+    lineno: usize,
+    /* Offset of the start of each line in chars. */
+    line_starts: &'a [usize],
+}
+
+/*
+ * Return the index just past the close character matching the open
+ * character at "start", skipping over anything quoted or escaped.  Quotes
+ * and backquotes are their own close character.
+ */
+fn balanced(chars: &[char], start: usize, open: char, close: char) -> usize {
+    let mut i = start + 1;
+    if open == close {
+        while i < chars.len() {
+            if chars[i] == '\\' && open != '\'' {
+                i += 1;
+            } else if chars[i] == close {
+                return i + 1;
+            }
+            i += 1;
+        }
+        return chars.len();
+    }
+    let mut depth = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            c @ ('\'' | '"' | '`') => i = balanced(chars, i, c, c) - 1,
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+impl Lexer<'_> {
+    fn loc(&self, pos: usize) -> Loc {
+        let lineno = self.line_starts.partition_point(|&s| s <= pos);
+        Loc { lineno, column: pos - self.line_starts[lineno - 1] + 1 }
+    }
+
+    /*
+     * Add "s" to the code, where the source for it starts at "start".  Any
+     * chars in "s" past the end of the source for it are given the location
+     * of the last source char.
+     */
+    fn emit(&self, code: &mut Code, s: &str, start: usize, len: usize) {
+        for (i, c) in s.chars().enumerate() {
+            code.text.push(c);
+            code.locs.push(self.loc(start + i.min(len.saturating_sub(1))));
+        }
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.chars.get(self.pos + n).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.peek(i) == Some(c))
+    }
+
+    /*
+     * Consume chars up to "end", counting newlines, and return them.
+     */
+    fn take(&mut self, end: usize) -> String {
+        let end = end.min(self.chars.len());
+        let s: String = self.chars[self.pos..end].iter().collect();
+        self.lineno += s.matches('\n').count();
+        self.pos = end;
+        s
+    }
+
+    /*
+     * Lex a single word starting at the current position.
+     */
+    fn word(&mut self, code: &mut Code, substs: &mut Vec<LogicalLine>) -> Word {
+        let mut w = Word::default();
+        while let Some(c) = self.peek(0) {
+            let start = self.pos;
+            match c {
+                ' ' | '\t' | '\n' => break,
+                '\\' if self.peek(1) == Some('\n') => {
+                    self.pos += 2;
+                    self.lineno += 1;
+                }
+                '\\' => {
+                    let s = self.take(self.pos + 2);
+                    w.raw.push_str(&s);
+                    w.text.push_str(&s[1..]);
+                    w.quoted = true;
+                    let len = s.chars().count();
+                    self.emit(code, &" ".repeat(len), start, len);
+                }
+                '\'' => {
+                    let end = balanced(self.chars, self.pos, '\'', '\'');
+                    let s = self.take(end);
+                    w.raw.push_str(&s);
+                    let inner = s[1..].strip_suffix('\'').unwrap_or(&s[1..]);
+                    w.text.push_str(inner);
+                    w.quoted = true;
+                    let blank = " ".repeat(inner.chars().count());
+                    let len = s.chars().count();
+                    self.emit(code, &format!("'{blank}'"), start, len);
+                }
+                '$' if self.peek(1) == Some('\'') => {
+                    /*
+                     * $'...' allows backslash-escaped single quotes.
+                     */
+                    let mut end = self.pos + 2;
+                    while end < self.chars.len() && self.chars[end] != '\'' {
+                        if self.chars[end] == '\\' {
+                            end += 1;
+                        }
+                        end += 1;
+                    }
+                    let s = self.take(end + 1);
+                    w.raw.push_str(&s);
+                    let inner = s[2..].strip_suffix('\'').unwrap_or(&s[2..]);
+                    w.text.push_str(inner);
+                    w.quoted = true;
+                    w.ansi_c = true;
+                    let blank = " ".repeat(inner.chars().count());
+                    let len = s.chars().count();
+                    self.emit(code, &format!("$'{blank}'"), start, len);
+                }
+                '"' => {
+                    let end = self.double_quote_end(substs);
+                    let s = self.take(end);
+                    w.raw.push_str(&s);
+                    let inner = s[1..].strip_suffix('"').unwrap_or(&s[1..]);
+                    let mut chars = inner.chars().peekable();
+                    while let Some(c) = chars.next() {
+                        if c == '\\'
+                            && matches!(
+                                chars.peek(),
+                                Some('$' | '`' | '"' | '\\' | '\n')
+                            )
+                        {
+                            if let Some(n) = chars.next() {
+                                if n != '\n' {
+                                    w.text.push(n);
+                                }
+                            }
+                            continue;
+                        }
+                        w.text.push(c);
+                    }
+                    w.quoted = true;
+                    let chars: Vec<char> = s.chars().collect();
+                    let mut i = 0;
+                    while i < chars.len() {
+                        if chars[i] == '\\' && chars.get(i + 1) == Some(&'\n') {
+                            i += 2;
+                            continue;
+                        }
+                        code.text.push(chars[i]);
+                        code.locs.push(self.loc(start + i));
+                        i += 1;
+                    }
+                }
+                '$' if matches!(self.peek(1), Some('(' | '{')) => {
+                    let (open, close) = match self.peek(1) {
+                        Some('(') => ('(', ')'),
+                        _ => ('{', '}'),
+                    };
+                    let end = balanced(self.chars, self.pos + 1, open, close);
+                    if open == '(' {
+                        self.substitution(self.pos, end, substs);
+                    }
+                    let s = self.take(end);
+                    w.raw.push_str(&s);
+                    w.text.push_str(&s);
+                    self.emit(code, &s, start, s.chars().count());
+                }
+                '`' => {
+                    let end = balanced(self.chars, self.pos, '`', '`');
+                    self.substitution(self.pos, end, substs);
+                    let s = self.take(end);
+                    w.raw.push_str(&s);
+                    w.text.push_str(&s);
+                    self.emit(code, &s, start, s.chars().count());
+                }
+                '(' if w.raw.ends_with('=') && !w.quoted => {
+                    /*
+                     * Array assignment, name=(...), kept as a single word.
+                     */
+                    let end = balanced(self.chars, self.pos, '(', ')');
+                    let s = self.take(end);
+                    w.raw.push_str(&s);
+                    w.text.push_str(&s);
+                    self.emit(code, &s, start, s.chars().count());
+                }
+                c if OPERATORS.iter().any(|op| op.starts_with(c)) => break,
+                c => {
+                    self.pos += 1;
+                    w.raw.push(c);
+                    w.text.push(c);
+                    code.text.push(c);
+                    code.locs.push(self.loc(start));
+                }
+            }
+        }
+        w
+    }
+
+    /*
+     * Return the end of a double quoted string, lexing any command
+     * substitutions found inside it.
+     */
+    fn double_quote_end(&self, substs: &mut Vec<LogicalLine>) -> usize {
+        let mut i = self.pos + 1;
+        while i < self.chars.len() {
+            match self.chars[i] {
+                '\\' => i += 1,
+                '"' => return i + 1,
+                '$' if self.chars.get(i + 1) == Some(&'(') => {
+                    let end = balanced(self.chars, i + 1, '(', ')');
+                    self.substitution(i, end, substs);
+                    i = end - 1;
+                }
+                '$' if self.chars.get(i + 1) == Some(&'{') => {
+                    i = balanced(self.chars, i + 1, '{', '}') - 1;
+                }
+                '`' => {
+                    let end = balanced(self.chars, i, '`', '`');
+                    self.substitution(i, end, substs);
+                    i = end - 1;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        self.chars.len()
+    }
+
+    /*
+     * Lex the body of the $(...) or backquote command substitution at
+     * "start", which ends just before "end".  $((...)) is arithmetic
+     * expansion rather than a command, and is ignored.
+     */
+    fn substitution(
+        &self,
+        start: usize,
+        end: usize,
+        substs: &mut Vec<LogicalLine>,
+    ) {
+        let (body, close) = match self.chars[start] {
+            '`' => (start + 1, '`'),
+            _ if self.chars.get(start + 2) == Some(&'(') => return,
+            _ => (start + 2, ')'),
+        };
+        let end = if end > body && self.chars[end - 1] == close {
+            end - 1
+        } else {
+            end
+        };
+        let mut lexer = Lexer {
+            chars: &self.chars[..end],
+            pos: body,
+            lineno: self.loc(body).lineno,
+            line_starts: self.line_starts,
+        };
+        substs.extend(lexer.run());
+    }
+
+    /*
+     * Skip over heredoc bodies following a newline.
+     */
+    fn heredocs(&mut self, pending: &mut Vec<(String, bool)>) {
+        for (delim, strip_tabs) in pending.drain(..) {
+            while self.pos < self.chars.len() {
+                let end = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '\n')
+                    .map_or(self.chars.len(), |n| self.pos + n + 1);
+                let line = self.take(end);
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    line
+                };
+                if line == delim {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn run(&mut self) -> Vec<LogicalLine> {
+        let mut lines = vec![];
+        let mut cur = LogicalLine { lineno: self.lineno, ..Default::default() };
+        let mut code = Code::default();
+        let mut start = self.pos;
+        let mut pending: Vec<(String, bool)> = vec![];
+        let mut delim: Option<bool> = None;
+        let mut commented = false;
+
+        while let Some(c) = self.peek(0) {
+            match c {
+                '\\' if self.peek(1) == Some('\n') => {
+                    self.pos += 2;
+                    self.lineno += 1;
+                }
+                '\n' => {
+                    self.finish(
+                        &mut lines, &mut cur, &mut code, start, commented,
+                    );
+                    commented = false;
+                    self.pos += 1;
+                    self.lineno += 1;
+                    self.heredocs(&mut pending);
+                    cur.lineno = self.lineno;
+                    start = self.pos;
+                }
+                ' ' | '\t' => {
+                    code.text.push(c);
+                    code.locs.push(self.loc(self.pos));
+                    self.pos += 1;
+                }
+                '#' => {
+                    let end = self.chars[self.pos..]
+                        .iter()
+                        .position(|&c| c == '\n')
+                        .map_or(self.chars.len(), |n| self.pos + n);
+                    cur.comments.push(self.take(end));
+                    commented = true;
+                }
+                _ => {
+                    let pos = self.pos;
+                    let op = OPERATORS.iter().find(|op| self.starts_with(op));
+                    let word = match op {
+                        Some(op) => {
+                            let len = op.chars().count();
+                            self.emit(&mut code, op, pos, len);
+                            self.pos += len;
+                            Word {
+                                raw: op.to_string(),
+                                text: op.to_string(),
+                                op: true,
+                                ..Default::default()
+                            }
+                        }
+                        None => {
+                            let n = cur.substs.len();
+                            let word = self.word(&mut code, &mut cur.substs);
+                            for line in &mut cur.substs[n..] {
+                                line.within = pos;
+                            }
+                            word
+                        }
+                    };
+                    let word = Word { pos, loc: self.loc(pos), ..word };
+                    if word.is_op("<<") || word.is_op("<<-") {
+                        delim = Some(word.is_op("<<-"));
+                    } else if let Some(strip_tabs) = delim.take() {
+                        pending.push((word.text.clone(), strip_tabs));
+                    }
+                    cur.words.push(word);
+                }
+            }
+        }
+        self.finish(&mut lines, &mut cur, &mut code, start, commented);
+        lines
+    }
+
+    fn finish(
+        &self,
+        lines: &mut Vec<LogicalLine>,
+        cur: &mut LogicalLine,
+        code: &mut Code,
+        start: usize,
+        commented: bool,
+    ) {
+        let code = std::mem::take(code);
+        if !cur.words.is_empty() {
+            let src: String = self.chars[start..self.pos].iter().collect();
+            cur.text = src
+                .lines()
+                .map(|l| l.trim().trim_end_matches('\\').trim_end())
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            /*
+             * Trim the code, and the locations along with it.
+             */
+            let trimmed = code.text.trim_start();
+            let skip = code.text.chars().count() - trimmed.chars().count();
+            let trimmed = trimmed.trim_end();
+            cur.code = trimmed.to_string();
+            cur.code_locs = code
+                .locs
+                .into_iter()
+                .skip(skip)
+                .take(trimmed.chars().count())
+                .collect();
+            lines.push(std::mem::take(cur));
+        } else if !commented {
+            cur.comments.clear();
+        }
+    }
+}
+
+/*
+ * Split a script into logical lines.
+ */
+pub fn lex(src: &str) -> Vec<LogicalLine> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut line_starts = vec![0];
+    line_starts.extend(
+        chars
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == '\n')
+            .map(|(i, _)| i + 1),
+    );
+    Lexer { chars: &chars, pos: 0, lineno: 1, line_starts: &line_starts }.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &LogicalLine) -> Vec<&str> {
+        line.words.iter().map(|w| w.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_words() {
+        let lines = lex("if [ 'foo bar' == \"a $b\" ]; then # comment\n");
+        assert_eq!(lines.len(), 1);
+        assert_eq!(
+            texts(&lines[0]),
+            ["if", "[", "foo bar", "==", "a $b", "]", ";", "then"]
+        );
+        assert!(lines[0].words[6].op);
+        assert_eq!(lines[0].words[3].pos, 15);
+        assert_eq!(lines[0].code, "if [ '       ' == \"a $b\" ]; then");
+
+        let lines =
+            lex("echo a\\ b \\$RANDOM x=$(echo \")\") ${a:-'}'}&>/dev/null");
+        assert_eq!(
+            texts(&lines[0]),
+            [
+                "echo",
+                "a b",
+                "$RANDOM",
+                "x=$(echo \")\")",
+                "${a:-'}'}",
+                "&>",
+                "/dev/null"
+            ]
+        );
+        assert!(!lines[0].code.contains("$RANDOM"));
+
+        let lines = lex("a=(1 2) b=$'\\'x' c=`d`");
+        assert_eq!(texts(&lines[0]), ["a=(1 2)", "b=\\'x", "c=`d`"]);
+        assert!(lines[0].words[1].ansi_c);
+
+        /*
+         * A "#" within a word is not a comment.
+         */
+        let lines = lex("echo a#b ${#c} $#");
+        assert_eq!(texts(&lines[0]), ["echo", "a#b", "${#c}", "$#"]);
+    }
+
+    #[test]
+    fn test_lines() {
+        let src = "\
+#!/bin/sh
+# comment
+
+if [ \"$a\" \\
+    == b ]; then
+  echo 'multi
+line'
+fi
+cat <<EOF; cat <<-'END'
+[ a == b ]
+EOF
+\t[[ x ]]
+\tEND
+done
+";
+        let lines = lex(src);
+        let got: Vec<(usize, &str)> =
+            lines.iter().map(|l| (l.lineno, l.text.as_str())).collect();
+        assert_eq!(
+            got,
+            [
+                (4, "if [ \"$a\" == b ]; then"),
+                (6, "echo 'multi line'"),
+                (8, "fi"),
+                (9, "cat <<EOF; cat <<-'END'"),
+                (14, "done"),
+            ]
+        );
+        assert_eq!(
+            texts(&lines[0]),
+            ["if", "[", "$a", "==", "b", "]", ";", "then"]
+        );
+        assert_eq!(texts(&lines[1]), ["echo", "multi\nline"]);
+        let loc = |lineno, column| Loc { lineno, column };
+        assert_eq!(
+            lines.iter().map(|l| l.words[0].loc).collect::<Vec<_>>(),
+            [loc(4, 1), loc(6, 3), loc(8, 1), loc(9, 1), loc(14, 1)]
+        );
+        assert_eq!(lines[0].words[3].loc, loc(5, 5));
+        assert_eq!(lines[1].code_locs.last(), Some(&loc(7, 5)));
+
+        /*
+         * Every char of code has a location, with continuations removed.
+         */
+        let lines = lex("  x \\\n\"a\\\nb\"$'c' 'd'\n");
+        assert_eq!(lines[0].code, "x \"ab\"$' ' ' '");
+        assert_eq!(lines[0].code.chars().count(), lines[0].code_locs.len());
+        assert_eq!(lines[0].code_locs[0], loc(1, 3));
+        assert_eq!(lines[0].code_locs[2], loc(2, 1));
+        assert_eq!(lines[0].code_locs[4], loc(3, 1));
+    }
+
+    #[test]
+    fn test_substitutions() {
+        let src = "x=$(a \"$(b)\" `c`) \"`d; e`\" $((f)) '$(g)'\n";
+        let lines = lex(src);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(
+            texts(&lines[0]),
+            ["x=$(a \"$(b)\" `c`)", "`d; e`", "$((f))", "$(g)"]
+        );
+        fn nested<'a>(line: &'a LogicalLine, out: &mut Vec<Vec<&'a str>>) {
+            for l in &line.substs {
+                out.push(texts(l));
+                nested(l, out);
+            }
+        }
+        let mut found = vec![];
+        nested(&lines[0], &mut found);
+        assert_eq!(
+            lines[0].substs.iter().map(|l| l.within).collect::<Vec<_>>(),
+            [0, 18]
+        );
+        assert_eq!(
+            found,
+            [
+                vec!["a", "$(b)", "`c`"],
+                vec!["b"],
+                vec!["c"],
+                vec!["d", ";", "e"]
+            ]
+        );
+
+        /*
+         * Locations are within the original source.
+         */
+        let lines = lex("echo \\\n  $(\n  [ a == b ])\n");
+        let inner = &lines[0].substs[0];
+        assert_eq!(inner.words[2].loc, Loc { lineno: 3, column: 7 });
+    }
+
+    #[test]
+    fn test_comments() {
+        let src = "\
+# unrelated
+
+# above
+echo a # trailing
+echo b
+";
+        let lines = lex(src);
+        assert_eq!(lines[0].comments, ["# above", "# trailing"]);
+        assert!(lines[1].comments.is_empty());
+    }
+}
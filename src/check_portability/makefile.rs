@@ -0,0 +1,440 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Extract the shell commands from Makefile recipes so that they can be fed
+ * through the same checks as shell scripts.
+ *
+ * Make variable references, e.g. $(CC) or ${.TARGET}, and autoconf
+ * substitutions such as @SHELL@, are replaced with a placeholder word, and
+ * "$$" is converted to the "$" that the shell sees.
+ */
+
+use super::lexer::Loc;
+use std::iter;
+
+/*
+ * Replaces any make variable reference, so that bmake modifiers such as
+ * ${VAR:S/a/b/} do not look like shell expansions.
+ */
+const PLACEHOLDER: &str = "MAKEVAR";
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Recipe {
+    /* Line number of the first line of the command. */
+    pub lineno: usize,
+    /* Original text for display, with continuation lines joined. */
+    pub text: String,
+    /* The command as passed to the shell. */
+    pub shell: String,
+    /* Location in the Makefile of each char in shell. */
+    pub locs: Vec<Loc>,
+    /* Comments on the lines directly above the command. */
+    pub comments: Vec<String>,
+}
+
+impl Recipe {
+    /*
+     * Map a location in the shell command to the Makefile.  Placeholders
+     * map to the start of the variable reference that they replaced.
+     */
+    pub fn loc(&self, loc: Loc) -> Loc {
+        let start: usize = self
+            .shell
+            .split('\n')
+            .take(loc.lineno.saturating_sub(1))
+            .map(|l| l.chars().count() + 1)
+            .sum();
+        let pos = start + loc.column.saturating_sub(1);
+        self.locs.get(pos).or(self.locs.last()).copied().unwrap_or(loc)
+    }
+}
+
+/*
+ * Chars of a line, along with their location.
+ */
+fn located(
+    line: &str,
+    lineno: usize,
+    column: usize,
+) -> impl Iterator<Item = (char, Loc)> + '_ {
+    line.chars()
+        .enumerate()
+        .map(move |(i, c)| (c, Loc { lineno, column: column + i }))
+}
+
+/*
+ * Whether a filename looks like a Makefile (or automake/autoconf input).
+ */
+pub fn is_makefile(fname: &str) -> bool {
+    let base = fname
+        .strip_suffix(".in")
+        .or_else(|| fname.strip_suffix(".am"))
+        .unwrap_or(fname);
+    matches!(base, "Makefile" | "makefile" | "GNUmakefile")
+        || fname.ends_with(".mk")
+}
+
+fn continues(line: &str) -> bool {
+    line.bytes().rev().take_while(|&b| b == b'\\').count() % 2 == 1
+}
+
+fn is_directive(line: &str) -> bool {
+    let line = line.trim_start();
+    let word = line
+        .strip_prefix('.')
+        .map(|l| l.trim_start())
+        .unwrap_or(line)
+        .split(|c: char| c.is_whitespace() || c == '(')
+        .next()
+        .unwrap_or("");
+    matches!(
+        word,
+        "if" | "ifdef"
+            | "ifndef"
+            | "ifmake"
+            | "ifnmake"
+            | "ifeq"
+            | "ifneq"
+            | "elif"
+            | "elifdef"
+            | "elifndef"
+            | "else"
+            | "endif"
+            | "for"
+            | "endfor"
+    )
+}
+
+/*
+ * If this is a rule line, return any inline recipe following ";".
+ */
+fn rule(line: &str) -> Option<Option<&str>> {
+    if line.starts_with('#') || line.starts_with('.') && !line.contains(':') {
+        return None;
+    }
+    let colon = line.find(':')?;
+    if line[..colon].contains('=') || line[colon..].starts_with(":=") {
+        return None;
+    }
+    if line[colon..].starts_with("::=") {
+        return None;
+    }
+    Some(line[colon..].split_once(';').map(|(_, r)| r))
+}
+
+/*
+ * Convert a single recipe command to the text that make passes to the
+ * shell, keeping track of where each char came from.
+ */
+fn shell_text(chars: &[(char, Loc)]) -> Vec<(char, Loc)> {
+    let placeholder = |loc| PLACEHOLDER.chars().map(move |c| (c, loc));
+    let mut out = vec![];
+    let mut i = 0;
+
+    /*
+     * Autoconf @VAR@ substitutions are replaced first, as one at the start
+     * of the command would otherwise be confused with the "@" prefix.
+     */
+    let mut subst = vec![];
+    while i < chars.len() {
+        if chars[i].0 == '@' {
+            let len = chars[i + 1..]
+                .iter()
+                .take_while(|(c, _)| c.is_ascii_alphanumeric() || *c == '_')
+                .count();
+            if len > 0 && chars.get(i + 1 + len).map(|c| c.0) == Some('@') {
+                subst.extend(placeholder(chars[i].1));
+                i += len + 2;
+                continue;
+            }
+        }
+        subst.push(chars[i]);
+        i += 1;
+    }
+
+    let skip = subst
+        .iter()
+        .position(|(c, _)| !c.is_whitespace())
+        .unwrap_or(subst.len());
+    let chars: Vec<(char, Loc)> = subst[skip..]
+        .iter()
+        .skip_while(|(c, _)| matches!(c, '@' | '-' | '+' | ' ' | '\t'))
+        .copied()
+        .collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (c, loc) = chars[i];
+        if c != '$' {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        match chars.get(i + 1).map(|c| c.0) {
+            Some('$') => {
+                out.push(('$', loc));
+                i += 2;
+            }
+            Some(open @ ('(' | '{')) => {
+                let close = if open == '(' { ')' } else { '}' };
+                let mut depth = 0;
+                while i < chars.len() {
+                    i += 1;
+                    match chars.get(i).map(|c| c.0) {
+                        Some(c) if c == open => depth += 1,
+                        Some(c) if c == close => {
+                            depth -= 1;
+                            if depth == 0 {
+                                i += 1;
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                out.extend(placeholder(loc));
+            }
+            Some('\n') | None => {
+                out.push(('$', loc));
+                i += 1;
+            }
+            Some(_) => {
+                out.extend(placeholder(loc));
+                i += 2;
+            }
+        }
+    }
+    out
+}
+
+fn recipe(
+    lineno: usize,
+    text: String,
+    cmd: &[(char, Loc)],
+    comments: Vec<String>,
+) -> Recipe {
+    let (shell, locs) = shell_text(cmd).into_iter().unzip();
+    Recipe { lineno, text, shell, locs, comments }
+}
+
+/*
+ * Return every recipe command in a Makefile.
+ */
+pub fn recipes(contents: &str) -> Vec<Recipe> {
+    let mut recipes = vec![];
+    let mut lines = contents.lines().enumerate();
+    let mut in_rule = false;
+    let mut comments: Vec<String> = vec![];
+
+    while let Some((i, line)) = lines.next() {
+        /*
+         * Gather any continuation lines.
+         */
+        let mut full = vec![line];
+        while continues(full[full.len() - 1]) {
+            match lines.next() {
+                Some((_, l)) => full.push(l),
+                None => break,
+            }
+        }
+
+        if let Some(cmd) = line.strip_prefix('\t').filter(|_| in_rule) {
+            if cmd.trim_start().starts_with('#') {
+                comments.push(cmd.trim().to_string());
+                continue;
+            }
+            /*
+             * Make removes a leading tab from each continuation line of a
+             * recipe, but otherwise passes the text to the shell as is.
+             */
+            let mut cmdlocs: Vec<(char, Loc)> =
+                located(cmd, i + 1, 2).collect();
+            for (n, l) in full.iter().enumerate().skip(1) {
+                let prev = Loc {
+                    lineno: i + n,
+                    column: full[n - 1].chars().count() + 1,
+                };
+                cmdlocs.push(('\n', prev));
+                match l.strip_prefix('\t') {
+                    Some(l) => cmdlocs.extend(located(l, i + n + 1, 2)),
+                    None => cmdlocs.extend(located(l, i + n + 1, 1)),
+                }
+            }
+            let text = full
+                .iter()
+                .map(|l| l.trim().trim_end_matches('\\').trim_end())
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            recipes.push(recipe(
+                i + 1,
+                text,
+                &cmdlocs,
+                std::mem::take(&mut comments),
+            ));
+            continue;
+        }
+
+        let joined = full
+            .iter()
+            .map(|l| l.trim_end_matches('\\'))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if joined.trim_start().starts_with('#') {
+            comments.push(joined.trim().to_string());
+            continue;
+        }
+        if joined.trim().is_empty() {
+            comments.clear();
+            continue;
+        }
+        if is_directive(&joined) {
+            continue;
+        }
+        match rule(&joined) {
+            Some(inline) => {
+                in_rule = true;
+                if let Some(cmd) = inline.filter(|c| !c.trim().is_empty()) {
+                    /*
+                     * The inline command is the end of the joined line, so
+                     * take the same number of chars from the end of it.
+                     */
+                    let joinedlocs: Vec<(char, Loc)> = full
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(n, l)| {
+                            let l = l.trim_end_matches('\\');
+                            let end = Loc {
+                                lineno: i + n + 1,
+                                column: l.chars().count() + 1,
+                            };
+                            located(l, i + n + 1, 1)
+                                .chain(iter::once((' ', end)))
+                        })
+                        .collect();
+                    let start = joined.chars().count() - cmd.chars().count();
+                    let len = cmd.chars().count();
+                    recipes.push(recipe(
+                        i + 1,
+                        joined.trim().to_string(),
+                        &joinedlocs[start..start + len],
+                        std::mem::take(&mut comments),
+                    ));
+                }
+            }
+            None => in_rule = false,
+        }
+        comments.clear();
+    }
+    recipes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_makefile() {
+        for f in
+            ["Makefile", "Makefile.in", "Makefile.am", "GNUmakefile", "x.mk"]
+        {
+            assert!(is_makefile(f), "{f}");
+        }
+        for f in ["Makefile.PL", "configure", "makefile.txt", "mk"] {
+            assert!(!is_makefile(f), "{f}");
+        }
+    }
+
+    fn shell_text(cmd: &str) -> String {
+        let cmd: Vec<(char, Loc)> =
+            cmd.chars().map(|c| (c, Loc::default())).collect();
+        super::shell_text(&cmd).into_iter().map(|(c, _)| c).collect()
+    }
+
+    #[test]
+    fn test_shell_text() {
+        assert_eq!(shell_text("@echo $@"), "echo MAKEVAR");
+        assert_eq!(
+            shell_text("-@$(CC) -o $@ ${OBJS:S/a/b/}"),
+            "MAKEVAR -o MAKEVAR MAKEVAR"
+        );
+        assert_eq!(
+            shell_text("for f in *; do echo $$f; done"),
+            "for f in *; do echo $f; done"
+        );
+        assert_eq!(shell_text("@MKDIR_P@ $(DESTDIR)"), "MAKEVAR MAKEVAR");
+        assert_eq!(shell_text("x=$$((1 + $(N)))"), "x=$((1 + MAKEVAR))");
+    }
+
+    #[test]
+    fn test_recipes() {
+        let mk = "\
+# comment
+SRCS=	a.c \\
+\tb.c
+OBJ:=	foo.o
+
+all: foo
+\t@echo $$RANDOM
+.if defined(FOO)
+\tif [ a == b ]; then \\
+\t  :; \\
+\tfi
+.endif
+
+\t-cp a b
+VAR=	1
+\tnot a recipe
+install: ; [[ -d x ]]
+";
+        let r = recipes(mk);
+        let got: Vec<(usize, &str)> =
+            r.iter().map(|r| (r.lineno, r.shell.as_str())).collect();
+        assert_eq!(
+            got,
+            [
+                (7, "echo $RANDOM"),
+                (9, "if [ a == b ]; then \\\n  :; \\\nfi"),
+                (14, "cp a b"),
+                (17, "[[ -d x ]]"),
+            ]
+        );
+        assert_eq!(r[1].text, "if [ a == b ]; then :; fi");
+    }
+
+    /*
+     * Locations in the shell command map back to the Makefile, including
+     * after make variables and continuation lines without a tab.
+     */
+    #[test]
+    fn test_recipe_locs() {
+        let mk = "all:\n\t@$(CC) $$x \\\n  ${Y} @Z@ z\nx: ; @$(A) a\n";
+        let r = recipes(mk);
+        let loc = |r: &Recipe, lineno, column| r.loc(Loc { lineno, column });
+        let at = |lineno, column| Loc { lineno, column };
+        assert_eq!(r[0].shell, "MAKEVAR $x \\\n  MAKEVAR MAKEVAR z");
+        assert_eq!(loc(&r[0], 1, 1), at(2, 3));
+        assert_eq!(loc(&r[0], 1, 7), at(2, 3));
+        assert_eq!(loc(&r[0], 1, 9), at(2, 9));
+        assert_eq!(loc(&r[0], 1, 10), at(2, 11));
+        assert_eq!(loc(&r[0], 2, 3), at(3, 3));
+        assert_eq!(loc(&r[0], 2, 11), at(3, 8));
+        assert_eq!(loc(&r[0], 2, 19), at(3, 12));
+        assert_eq!(r[1].shell, "MAKEVAR a");
+        assert_eq!(loc(&r[1], 1, 1), at(4, 7));
+        assert_eq!(loc(&r[1], 1, 9), at(4, 12));
+    }
+}
@@ -0,0 +1,504 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Minimal unified diff support for writing pkgsrc patch-* files.
+ *
+ * As the file in WRKSRC has already had any existing patch applied, there
+ * is no need for a general diff algorithm.  The hunks of an existing patch
+ * describe exactly how the original file lines up with the current one, and
+ * fixes only ever change lines in place, so a new diff can be produced by
+ * combining the two.
+ */
+
+/*
+ * Lines of context either side of a change, as used by "diff -u".
+ */
+const CONTEXT: usize = 3;
+
+const NO_NEWLINE: &str = "\\ No newline at end of file\n";
+
+/*
+ * A line of a diff.  The text includes any trailing newline, so that a
+ * missing newline at the end of the file is preserved.
+ */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DiffLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug, Default)]
+struct Hunk {
+    new_start: usize,
+    lines: Vec<DiffLine>,
+}
+
+/*
+ * The changes to a single file within a patch.
+ */
+#[derive(Debug, Default)]
+pub struct FilePatch {
+    /* Path from the "+++" header. */
+    pub path: String,
+    /* Any text preceding the hunks, including the "---" and "+++" lines. */
+    header: String,
+    hunks: Vec<Hunk>,
+    /* Text of the header and hunks, as read or last regenerated. */
+    raw: String,
+}
+
+/*
+ * A parsed patch file.
+ */
+#[derive(Debug, Default)]
+pub struct Patch {
+    /* Text before the first file, i.e. the $NetBSD$ tag and comment. */
+    preamble: String,
+    pub files: Vec<FilePatch>,
+    trailer: String,
+}
+
+/*
+ * Name of the patch file for a path, following the pkgsrc convention of
+ * doubling any underscores and then replacing directory separators with
+ * underscores.
+ */
+pub fn patch_name(path: &str) -> String {
+    format!("patch-{}", path.replace('_', "__").replace('/', "_"))
+}
+
+/*
+ * Parse "@@ -a,b +c,d @@", returning (b, c, d).
+ */
+fn parse_range(line: &str) -> Option<(usize, usize, usize)> {
+    fn range(r: &str) -> Option<(usize, usize)> {
+        match r.split_once(',') {
+            Some((start, count)) => {
+                Some((start.parse().ok()?, count.parse().ok()?))
+            }
+            None => Some((r.parse().ok()?, 1)),
+        }
+    }
+    let mut ranges = line.strip_prefix("@@ ")?.split_whitespace();
+    let (_, old_count) = range(ranges.next()?.strip_prefix('-')?)?;
+    let (new_start, new_count) = range(ranges.next()?.strip_prefix('+')?)?;
+    Some((old_count, new_start, new_count))
+}
+
+/*
+ * Remove any timestamp from a "---" or "+++" header.  diff(1) separates it
+ * with a tab, but some tools use spaces.
+ */
+fn strip_timestamp(header: &str) -> &str {
+    let header = header.trim_end_matches(['\r', '\n']);
+    let path = header.split('\t').next().unwrap_or(header);
+    match path.split_once(' ') {
+        Some((p, rest))
+            if rest.trim_start().starts_with(|c: char| c.is_ascii_digit()) =>
+        {
+            p
+        }
+        _ => path,
+    }
+}
+
+/*
+ * Return the path of the file being patched, relative to WRKSRC, from the
+ * "---" and "+++" header lines (without their prefixes).  Patches created
+ * by git have "a/" and "b/" prefixes that are removed.
+ */
+fn header_path(old: &str, new: &str) -> String {
+    let old = strip_timestamp(old);
+    let new = strip_timestamp(new);
+    let new = match new.strip_prefix("b/") {
+        Some(p) if old.starts_with("a/") || old == "/dev/null" => p,
+        _ => new,
+    };
+    new.trim_start_matches("./").to_string()
+}
+
+impl Patch {
+    pub fn parse(text: &str) -> Patch {
+        let mut patch = Patch::default();
+        let mut pending = String::new();
+        let mut lines = text.split_inclusive('\n').peekable();
+
+        while let Some(line) = lines.next() {
+            if line.starts_with("--- ")
+                && lines.peek().is_some_and(|l| l.starts_with("+++ "))
+            {
+                let new = lines.next().unwrap_or_default();
+                let path = header_path(&line[4..], &new[4..]);
+                if patch.files.is_empty() {
+                    patch.preamble = std::mem::take(&mut pending);
+                }
+                let header =
+                    format!("{}{line}{new}", std::mem::take(&mut pending));
+                patch.files.push(FilePatch {
+                    path,
+                    raw: header.clone(),
+                    header,
+                    hunks: vec![],
+                });
+                continue;
+            }
+
+            let Some(file) = patch.files.last_mut() else {
+                pending.push_str(line);
+                continue;
+            };
+            let Some((mut old_left, new_start, mut new_left)) =
+                parse_range(line)
+            else {
+                pending.push_str(line);
+                continue;
+            };
+            file.raw.push_str(&pending);
+            file.raw.push_str(line);
+            pending.clear();
+
+            /*
+             * Consume the hunk body until both sides are complete.  Some
+             * editors strip the single space from empty context lines.
+             */
+            let mut hunk = Hunk { new_start, lines: vec![] };
+            while old_left > 0 || new_left > 0 {
+                let Some(l) = lines.next() else {
+                    break;
+                };
+                file.raw.push_str(l);
+                let dl = match l.as_bytes()[0] {
+                    b' ' | b'\n' => {
+                        old_left = old_left.saturating_sub(1);
+                        new_left = new_left.saturating_sub(1);
+                        DiffLine::Context(
+                            l.strip_prefix(' ').unwrap_or(l).into(),
+                        )
+                    }
+                    b'-' => {
+                        old_left = old_left.saturating_sub(1);
+                        DiffLine::Remove(l[1..].into())
+                    }
+                    b'+' => {
+                        new_left = new_left.saturating_sub(1);
+                        DiffLine::Add(l[1..].into())
+                    }
+                    b'\\' => continue,
+                    _ => break,
+                };
+                hunk.lines.push(dl);
+                if lines.peek().is_some_and(|l| l.starts_with('\\')) {
+                    file.raw.push_str(lines.next().unwrap_or_default());
+                    if let Some(
+                        DiffLine::Context(t)
+                        | DiffLine::Remove(t)
+                        | DiffLine::Add(t),
+                    ) = hunk.lines.last_mut()
+                    {
+                        if t.ends_with('\n') {
+                            t.pop();
+                        }
+                    }
+                }
+            }
+            file.hunks.push(hunk);
+        }
+        patch.trailer = pending;
+        patch
+    }
+
+    /*
+     * Create a new patch for a single file.
+     */
+    pub fn new(comment: &str, path: &str) -> Patch {
+        let header = format!("--- {path}.orig\n+++ {path}\n");
+        Patch {
+            preamble: format!("$NetBSD$\n\n{comment}\n\n"),
+            files: vec![FilePatch {
+                path: path.to_string(),
+                raw: header.clone(),
+                header,
+                hunks: vec![],
+            }],
+            trailer: String::new(),
+        }
+    }
+
+    pub fn file_mut(&mut self, path: &str) -> Option<&mut FilePatch> {
+        self.files.iter_mut().find(|f| f.path == path)
+    }
+}
+
+impl std::fmt::Display for Patch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.preamble)?;
+        for file in &self.files {
+            write!(f, "{}", file.raw)?;
+        }
+        write!(f, "{}", self.trailer)
+    }
+}
+
+impl FilePatch {
+    /*
+     * Line up the current contents of the file, which has already had this
+     * patch applied, with the original.  Returns None if the patch does not
+     * match the file.
+     */
+    fn align(&self, current: &[&str]) -> Option<Vec<DiffLine>> {
+        let mut lines = vec![];
+        let mut j = 0;
+        for hunk in &self.hunks {
+            let has_new =
+                hunk.lines.iter().any(|l| !matches!(l, DiffLine::Remove(_)));
+            let start =
+                hunk.new_start - usize::from(has_new && hunk.new_start > 0);
+            if start < j || start > current.len() {
+                return None;
+            }
+            lines.extend(
+                current[j..start]
+                    .iter()
+                    .map(|l| DiffLine::Context(l.to_string())),
+            );
+            j = start;
+            for l in &hunk.lines {
+                match l {
+                    DiffLine::Remove(_) => lines.push(l.clone()),
+                    DiffLine::Context(t) | DiffLine::Add(t) => {
+                        let cur = current.get(j)?;
+                        if cur.trim_end() != t.trim_end() {
+                            return None;
+                        }
+                        lines.push(match l {
+                            DiffLine::Add(_) => DiffLine::Add(cur.to_string()),
+                            _ => DiffLine::Context(cur.to_string()),
+                        });
+                        j += 1;
+                    }
+                }
+            }
+        }
+        lines.extend(
+            current[j..].iter().map(|l| DiffLine::Context(l.to_string())),
+        );
+        Some(lines)
+    }
+
+    /*
+     * Update the patch for new file contents, where "fixed" differs from
+     * "current" only by changes within lines.  Returns false if the existing
+     * patch does not match the current file.
+     */
+    pub fn update(&mut self, current: &str, fixed: &str) -> bool {
+        let current: Vec<&str> = current.split_inclusive('\n').collect();
+        let fixed: Vec<&str> = fixed.split_inclusive('\n').collect();
+        if current.len() != fixed.len() {
+            return false;
+        }
+        let Some(aligned) = self.align(&current) else {
+            return false;
+        };
+        let mut lines = vec![];
+        let mut j = 0;
+        for l in aligned {
+            match l {
+                DiffLine::Remove(_) => lines.push(l),
+                DiffLine::Context(t) => {
+                    if fixed[j] == t {
+                        lines.push(DiffLine::Context(t));
+                    } else {
+                        lines.push(DiffLine::Remove(t));
+                        lines.push(DiffLine::Add(fixed[j].to_string()));
+                    }
+                    j += 1;
+                }
+                DiffLine::Add(_) => {
+                    lines.push(DiffLine::Add(fixed[j].to_string()));
+                    j += 1;
+                }
+            }
+        }
+        self.raw = format!("{}{}", self.header, unified(&lines));
+        true
+    }
+}
+
+/*
+ * Format "start,count" for a hunk header, omitting a count of 1 as diff(1)
+ * does.
+ */
+fn range(start: usize, count: usize) -> String {
+    if count == 1 { start.to_string() } else { format!("{start},{count}") }
+}
+
+/*
+ * Generate unified diff hunks from a full list of lines.
+ */
+fn unified(lines: &[DiffLine]) -> String {
+    let mut old_before = vec![0];
+    let mut new_before = vec![0];
+    for l in lines {
+        let (o, n) = match l {
+            DiffLine::Context(_) => (1, 1),
+            DiffLine::Remove(_) => (1, 0),
+            DiffLine::Add(_) => (0, 1),
+        };
+        old_before.push(old_before[old_before.len() - 1] + o);
+        new_before.push(new_before[new_before.len() - 1] + n);
+    }
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| !matches!(l, DiffLine::Context(_)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut out = String::new();
+    let mut i = 0;
+    while i < changes.len() {
+        /*
+         * Merge changes that are close enough for their context to overlap.
+         */
+        let first = changes[i];
+        let mut last = first;
+        i += 1;
+        while i < changes.len() && changes[i] - last <= 2 * CONTEXT + 1 {
+            last = changes[i];
+            i += 1;
+        }
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + 1 + CONTEXT).min(lines.len());
+        let old_count = old_before[end] - old_before[start];
+        let new_count = new_before[end] - new_before[start];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_before[start] + usize::from(old_count > 0), old_count),
+            range(new_before[start] + usize::from(new_count > 0), new_count),
+        ));
+        for l in &lines[start..end] {
+            let (prefix, text) = match l {
+                DiffLine::Context(t) => (' ', t),
+                DiffLine::Remove(t) => ('-', t),
+                DiffLine::Add(t) => ('+', t),
+            };
+            out.push(prefix);
+            out.push_str(text);
+            if !text.ends_with('\n') {
+                out.push('\n');
+                out.push_str(NO_NEWLINE);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_name() {
+        assert_eq!(patch_name("configure"), "patch-configure");
+        assert_eq!(patch_name("src/build_all.sh"), "patch-src_build__all.sh");
+    }
+
+    #[test]
+    fn test_parse_paths() {
+        let text = "$NetBSD$\n\nTwo files.\n\n\
+                    --- sub/configure.orig\t2024-01-01 00:00:00\n\
+                    +++ sub/configure\t2024-01-02 00:00:00\n\
+                    @@ -1 +1 @@\n-a\n+b\n\
+                    diff --git a/src/x.sh b/src/x.sh\n\
+                    --- a/src/x.sh\n+++ b/src/x.sh\n\
+                    @@ -1 +1 @@\n-a\n+b\n\
+                    --- ./Makefile.in.orig 2024-01-01 00:00:00.000 +0000\n\
+                    +++ ./Makefile.in 2024-01-01 00:00:00.000 +0000\n\
+                    @@ -1 +1,2 @@\n a\n+b\n";
+        let patch = Patch::parse(text);
+        assert_eq!(
+            patch.files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+            ["sub/configure", "src/x.sh", "Makefile.in"]
+        );
+        assert_eq!(patch.to_string(), text);
+    }
+
+    #[test]
+    fn test_new_patch() {
+        let current: String = (1..=20).map(|i| format!("line {i}\n")).collect();
+        let fixed = current
+            .replace("line 2\n", "LINE 2\n")
+            .replace("line 15\n", "LINE 15\n");
+        let mut patch = Patch::new("Fix lines.", "a/b");
+        assert!(patch.files[0].update(&current, &fixed));
+        assert_eq!(
+            patch.to_string(),
+            "$NetBSD$\n\nFix lines.\n\n--- a/b.orig\n+++ a/b\n\
+             @@ -1,5 +1,5 @@\n line 1\n-line 2\n+LINE 2\n line 3\n line 4\n\
+             \x20line 5\n@@ -12,7 +12,7 @@\n line 12\n line 13\n line 14\n\
+             -line 15\n+LINE 15\n line 16\n line 17\n line 18\n"
+        );
+
+        /*
+         * Missing trailing newline.
+         */
+        let mut patch = Patch::new("x", "f");
+        assert!(patch.files[0].update("a\nb", "a\nc"));
+        assert!(patch.to_string().ends_with(
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n"
+        ));
+    }
+
+    #[test]
+    fn test_extend_patch() {
+        let orig = "$NetBSD$\n\nExisting.\n\n--- conf.orig\t2024-01-01\n+++ conf\n\
+                    @@ -2,3 +2,4 @@\n two\n-three\n+THREE\n+new\n four\n";
+        let patch = Patch::parse(orig);
+        assert_eq!(patch.files.len(), 1);
+        assert_eq!(patch.files[0].path, "conf");
+        assert_eq!(patch.to_string(), orig);
+
+        /*
+         * The existing change is merged with a new one close by, and one
+         * further away gets its own hunk.  A change to a line that was
+         * added by the existing patch stays as an addition.
+         */
+        let current = "one\ntwo\nTHREE\nnew\nfour\n5\n6\n7\n8\n9\n10\n11\n";
+        let fixed = "one\ntwo\nTHREE\nNEW\nfour\n5\nsix\n7\n8\n9\n10\n11\n";
+        let mut patch = Patch::parse(orig);
+        assert!(patch.files[0].update(current, fixed));
+        assert_eq!(
+            patch.to_string(),
+            "$NetBSD$\n\nExisting.\n\n--- conf.orig\t2024-01-01\n+++ conf\n\
+             @@ -1,9 +1,10 @@\n one\n two\n-three\n+THREE\n+NEW\n four\n 5\n-6\n+six\n 7\n 8\n 9\n"
+        );
+        let fixed = "one\ntwo\nTHREE\nnew\nfour\n5\n6\n7\n8\n9\n10\nELEVEN\n";
+        let mut patch = Patch::parse(orig);
+        assert!(patch.files[0].update(current, fixed));
+        assert!(patch.to_string().ends_with(
+            "@@ -1,6 +1,7 @@\n one\n two\n-three\n+THREE\n+new\n four\n 5\n\
+             \x206\n@@ -8,4 +9,4 @@\n 8\n 9\n 10\n-11\n+ELEVEN\n"
+        ));
+
+        /*
+         * A patch that does not match the current file is not touched.
+         */
+        let mut patch = Patch::parse(orig);
+        assert!(!patch.files[0].update("one\n", "ONE\n"));
+    }
+}
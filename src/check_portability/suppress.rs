@@ -0,0 +1,245 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Suppression of individual check matches.
+ *
+ * Inline suppressions are shell comments either on the same line as the
+ * match or on the line(s) directly above it, naming one or more check ids
+ * and an optional reason:
+ *
+ *   [ "$a" == "$b" ]  # mktool: ignore test-eq only ever run by bash
+ *
+ * A suppressions file instead lists one suppression per line, consisting of
+ * a path (or glob) relative to WRKDIR, a check id, an optional hash of the
+ * line text so that only one specific line is accepted, and a mandatory
+ * justification:
+ *
+ *   scripts/build.sh:test-eq:1a2b3c4d5e6f  only ever run by bash
+ *   tests/[a-z]*:random  test suite is not run
+ *
+ * The hash for a line is printed by --print-suppressions.
+ */
+
+use crate::hasher::MultiHasher;
+use pkgsrc::digest::Digest;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/*
+ * Number of hex characters of the line hash printed by --print-suppressions.
+ * Any prefix of at least MIN_HASH_LEN is accepted in a suppressions file.
+ */
+const HASH_LEN: usize = 12;
+const MIN_HASH_LEN: usize = 8;
+
+/*
+ * Hash of the text of a line, used to tie a suppression to a specific line
+ * independently of its line number.
+ */
+pub fn line_hash(text: &str) -> String {
+    let mut hasher = MultiHasher::new(&[Digest::SHA256]);
+    hasher.update(text.as_bytes());
+    let mut hash = hasher.finalize().hashes.remove(0);
+    hash.truncate(HASH_LEN);
+    hash
+}
+
+/*
+ * Parse a "# mktool: ignore <id>[,<id>...] [reason]" comment, returning the
+ * list of check ids.
+ */
+pub fn parse_inline(comment: &str) -> Option<Vec<String>> {
+    let rest = comment.trim_start_matches('#').trim_start();
+    let rest = rest.strip_prefix("mktool:")?.trim_start();
+    let rest = rest.strip_prefix("ignore")?;
+    if !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let ids = rest.split_whitespace().next()?;
+    Some(
+        ids.split(',')
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_string())
+            .collect(),
+    )
+}
+
+#[derive(Debug)]
+struct Entry {
+    lineno: usize,
+    pattern: glob::Pattern,
+    id: String,
+    hash: Option<String>,
+    used: bool,
+}
+
+#[derive(Debug)]
+pub struct Suppressions {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Suppressions {
+    /*
+     * Load a suppressions file.  Invalid lines are warned about and
+     * otherwise ignored.
+     */
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Suppressions> {
+        let path = path.as_ref().to_path_buf();
+        let contents = fs::read_to_string(&path)?;
+        let mut entries = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_entry(line) {
+                Ok((pattern, id, hash)) => entries.push(Entry {
+                    lineno: i + 1,
+                    pattern,
+                    id,
+                    hash,
+                    used: false,
+                }),
+                Err(e) => eprintln!(
+                    "WARNING: [check-portability] {}:{}: {e}",
+                    path.display(),
+                    i + 1
+                ),
+            }
+        }
+        Ok(Suppressions { path, entries })
+    }
+
+    /*
+     * Return whether a match is suppressed, recording the suppression as
+     * used if so.
+     */
+    pub fn matches(&mut self, path: &Path, id: &str, text: &str) -> bool {
+        let mut hash: Option<String> = None;
+        let mut found = false;
+        for e in &mut self.entries {
+            if e.id != id || !e.pattern.matches_path(path) {
+                continue;
+            }
+            if let Some(h) = &e.hash {
+                let hash = hash.get_or_insert_with(|| line_hash(text));
+                if !hash.starts_with(h.as_str()) {
+                    continue;
+                }
+            }
+            e.used = true;
+            found = true;
+        }
+        found
+    }
+
+    /*
+     * Print a warning for every entry that did not match anything.
+     */
+    pub fn warn_unused(&self) {
+        for e in self.entries.iter().filter(|e| !e.used) {
+            eprintln!(
+                "WARNING: [check-portability] {}:{}: unused suppression for {}",
+                self.path.display(),
+                e.lineno,
+                e.id
+            );
+        }
+    }
+}
+
+fn parse_entry(
+    line: &str,
+) -> Result<(glob::Pattern, String, Option<String>), String> {
+    let (spec, reason) =
+        line.split_once(char::is_whitespace).ok_or("missing justification")?;
+    if reason.trim().is_empty() {
+        return Err("missing justification".to_string());
+    }
+
+    /*
+     * Split from the right, as the path may itself contain ":".
+     */
+    let (rest, last) = spec.rsplit_once(':').ok_or("missing check id")?;
+    let (path, id, hash) = match rest.rsplit_once(':') {
+        Some((path, id))
+            if last.len() >= MIN_HASH_LEN
+                && last.bytes().all(|b| b.is_ascii_hexdigit()) =>
+        {
+            (path, id, Some(last.to_ascii_lowercase()))
+        }
+        _ => (rest, last, None),
+    };
+    if super::checks::find_check(id).is_none() {
+        return Err(format!("unknown check {id}"));
+    }
+    let pattern = glob::Pattern::new(path)
+        .map_err(|e| format!("invalid path '{path}': {e}"))?;
+    Ok((pattern, id.to_string(), hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_inline() {
+        assert_eq!(
+            parse_inline("# mktool: ignore test-eq"),
+            Some(vec!["test-eq".to_string()])
+        );
+        assert_eq!(
+            parse_inline("#mktool: ignore random,source  known ok"),
+            Some(vec!["random".to_string(), "source".to_string()])
+        );
+        assert_eq!(parse_inline("# mktool: ignored random"), None);
+        assert_eq!(parse_inline("# ignore random"), None);
+    }
+
+    #[test]
+    fn test_suppressions() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("suppressions");
+        let text = "[ a == b ]";
+        fs::write(
+            &file,
+            format!(
+                "# comment\n\
+                 a/b.sh:test-eq:{} reason\n\
+                 tests/*:random reason\n\
+                 c.sh:source:0000000000 reason\n\
+                 no-reason:random\n\
+                 bad:nosuchcheck reason\n",
+                line_hash(text)
+            ),
+        )?;
+        let mut s = Suppressions::open(&file)?;
+        assert_eq!(s.entries.len(), 3);
+        assert!(s.matches(Path::new("a/b.sh"), "test-eq", text));
+        assert!(!s.matches(Path::new("a/b.sh"), "test-eq", "[ c == d ]"));
+        assert!(!s.matches(Path::new("a/b.sh"), "random", text));
+        assert!(s.matches(Path::new("tests/foo"), "random", "x"));
+        assert!(!s.matches(Path::new("c.sh"), "source", "source x"));
+        assert_eq!(
+            s.entries.iter().map(|e| e.used).collect::<Vec<_>>(),
+            [true, true, false]
+        );
+        Ok(())
+    }
+}
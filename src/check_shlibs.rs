@@ -14,27 +14,48 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+mod dyld;
 #[cfg(all(unix, not(target_os = "macos")))]
 mod elf;
 #[cfg(target_os = "macos")]
 mod macho;
 
+use crate::build_thread_pool;
 use anyhow::Context;
 use clap::Args;
+use rayon::prelude::*;
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
 use std::io::{self, BufRead};
 use std::os::unix::ffi::OsStringExt;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[derive(Args, Debug)]
-pub struct CheckShlibs {}
+pub struct CheckShlibs {
+    #[arg(short = 'j', value_name = "jobs")]
+    #[arg(help = "Maximum number of threads (or \"MKTOOL_JOBS\" env var)")]
+    jobs: Option<usize>,
+
+    #[arg(long)]
+    #[arg(help = "Check undefined symbols are provided by dependencies (ELF)")]
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    symbols: bool,
+
+    #[arg(long)]
+    #[arg(help = "Report needed libraries that provide no symbols (ELF)")]
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    unused: bool,
+}
 
 /*
- * Shared state for checks.
+ * Shared state for checks.  Objects are checked in parallel, so the caches
+ * are behind a Mutex.
  */
 pub struct CheckState {
     /*
@@ -57,6 +78,18 @@ pub struct CheckState {
      */
     #[cfg_attr(target_os = "macos", allow(dead_code))]
     system_paths: Vec<PathBuf>,
+    /*
+     * What $LIB in an ELF RPATH expands to, if set by CHECK_SHLIBS_LIB.  This
+     * depends on how the system ld.so was configured, so cannot be guessed.
+     */
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    lib_token: Option<String>,
+    /*
+     * Set if any object had a $LIB that could not be expanded, so that a
+     * single warning can be printed once all objects have been checked.
+     */
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    lib_unexpanded: AtomicBool,
     /*
      * Where we built the package.  There should be no references to this path
      * at all in the final package.
@@ -71,7 +104,7 @@ pub struct CheckState {
      * The contents of pkgdb.byfile.db for file -> pkg lookups.  pkg is an
      * Option so that we can cache negative lookups.
      */
-    pkgdb: HashMap<PathBuf, Option<String>>,
+    pkgdb: Mutex<HashMap<PathBuf, Option<String>>>,
     /*
      * Path to pkg_admin and any arguments (usually "-K /path/to/pkgdb")
      */
@@ -88,26 +121,82 @@ pub struct CheckState {
     /*
      * Cache stat(2) lookups, storing whether file exists or not.
      */
-    statlibs: HashMap<PathBuf, bool>,
+    statlibs: Mutex<HashMap<PathBuf, bool>>,
+    /*
+     * Cache the symbols defined by each library, as "name" and, if it has a
+     * version, "name@version".  None if the library could not be parsed.
+     */
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    symbols: Mutex<HashMap<PathBuf, Option<Arc<HashSet<String>>>>>,
 }
 
-/**
- * See if this library path belongs to a package.  If it does, ensure
- * that the package is a runtime dependency.
+impl CheckState {
+    /*
+     * Return whether a library exists, using the cached result if we have
+     * already looked for it.  The lock is not held during the lookup, so
+     * two threads may occasionally both stat the same path.
+     */
+    pub fn lib_exists(&self, path: &Path) -> bool {
+        if let Ok(statlibs) = self.statlibs.lock() {
+            if let Some(e) = statlibs.get(path) {
+                return *e;
+            }
+        }
+        let e = path.exists();
+        if let Ok(mut statlibs) = self.statlibs.lock() {
+            statlibs.insert(path.to_path_buf(), e);
+        }
+        e
+    }
+
+    /*
+     * Return the directory that an object will be installed to.  Objects
+     * are passed to us inside DESTDIR.
+     */
+    pub fn install_dir(&self, path: &Path) -> PathBuf {
+        let path = match path.strip_prefix(&self.destdir) {
+            Ok(p) => Path::new("/").join(p),
+            Err(_) => path.to_path_buf(),
+        };
+        match path.parent() {
+            Some(p) => p.to_path_buf(),
+            None => PathBuf::from("/"),
+        }
+    }
+}
+
+/*
+ * Lexically normalise a path, removing "." and resolving ".." components, so
+ * that a path such as "/opt/pkg/bin/../lib" can be looked up in pkgdb.
  */
-fn check_pkg<P1, P2>(
-    obj: P1,
-    lib: P2,
-    state: &mut CheckState,
-) -> anyhow::Result<bool>
-where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-{
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            c => normal.push(c),
+        }
+    }
+    normal
+}
+
+/*
+ * Return the package that owns a library, if any.  Negative lookups are also
+ * cached.
+ */
+fn pkg_owner(lib: &Path, state: &CheckState) -> anyhow::Result<Option<String>> {
     /*
-     * On first lookup we need to initialise the pkgdb.
+     * On first lookup we need to initialise the pkgdb.  The lock is held
+     * while doing so, so that only one thread runs pkg_admin.
      */
-    if state.pkgdb.is_empty() {
+    let mut pkgdb = state
+        .pkgdb
+        .lock()
+        .map_err(|_| anyhow::anyhow!("pkgdb lock poisoned"))?;
+    if pkgdb.is_empty() {
         let cmd = Command::new(&state.pkg_admin_cmd)
             .args(&state.pkg_admin_args)
             .arg("dump")
@@ -154,7 +243,7 @@ where
                     Err(_) => continue,
                 };
 
-                state.pkgdb.insert(file, Some(pkg));
+                pkgdb.insert(file, Some(pkg));
             }
         }
     }
@@ -162,15 +251,33 @@ where
     /*
      * Look for an existing cached entry for this library.  If there is no
      * existing entry, or an existing entry that is None, then either way it
-     * is not a pkgsrc path and we should return early.
+     * is not a pkgsrc path.
      */
-    let pkgname = if let Some(entry) = state.pkgdb.get(lib.as_ref()) {
-        match entry {
-            Some(p) => p.to_string(),
-            None => return Ok(true),
-        }
-    } else {
-        state.pkgdb.insert(lib.as_ref().to_path_buf(), None);
+    if let Some(entry) = pkgdb.get(lib) {
+        return Ok(entry.clone());
+    }
+    pkgdb.insert(lib.to_path_buf(), None);
+    Ok(None)
+}
+
+/**
+ * See if this library path belongs to a package.  If it does, ensure
+ * that the package is a runtime dependency.
+ *
+ * Any failures are added to "out" rather than printed directly so that
+ * output can be printed in input order.
+ */
+fn check_pkg<P1, P2>(
+    obj: P1,
+    lib: P2,
+    state: &CheckState,
+    out: &mut Vec<String>,
+) -> anyhow::Result<bool>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let Some(pkgname) = pkg_owner(lib.as_ref(), state)? else {
         return Ok(true);
     };
 
@@ -196,17 +303,22 @@ where
      * Only issue an error if the package was listed in the depends file.
      */
     if found {
-        println!(
+        out.push(format!(
             "{}: {}: {} is not a runtime dependency",
             obj.as_ref().display(),
             lib.as_ref().display(),
             pkgname
-        );
+        ));
     }
     Ok(false)
 }
 
-fn check_shlib<P1, P2>(obj: P1, lib: P2, state: &CheckState) -> bool
+fn check_shlib<P1, P2>(
+    obj: P1,
+    lib: P2,
+    state: &CheckState,
+    out: &mut Vec<String>,
+) -> bool
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
@@ -219,11 +331,11 @@ where
      * Library paths must not start with WRKDIR.
      */
     if lib.starts_with(&state.wrkdir) {
-        println!(
+        out.push(format!(
             "{}: path relative to WRKDIR: {}",
             obj.display(),
             lib.display()
-        );
+        ));
         rv = false;
     }
 
@@ -232,12 +344,12 @@ where
      */
     for dir in &state.wrkref {
         if lib.starts_with(dir) {
-            println!(
+            out.push(format!(
                 "{}: rpath {} relative to CHECK_WRKREF_EXTRA_DIRS directory {}",
                 obj.display(),
                 lib.display(),
                 dir.display()
-            );
+            ));
             rv = false;
         }
     }
@@ -249,12 +361,12 @@ where
     let lib_str = lib.to_string_lossy();
     for regex in &state.toxic {
         if regex.is_match(&lib_str) {
-            println!(
+            out.push(format!(
                 "{}: resolved path {} matches toxic {}",
                 obj.display(),
                 lib.display(),
                 regex
-            );
+            ));
             rv = false;
         }
     }
@@ -263,7 +375,11 @@ where
      * Library paths must be absolute.
      */
     if !lib.starts_with("/") {
-        println!("{}: relative library path: {}", obj.display(), lib.display());
+        out.push(format!(
+            "{}: relative library path: {}",
+            obj.display(),
+            lib.display()
+        ));
         rv = false;
     }
 
@@ -361,6 +477,7 @@ impl CheckShlibs {
                 system_paths.push(path);
             }
         }
+        let lib_token = std::env::var("CHECK_SHLIBS_LIB").ok();
         let toxic = match std::env::var("CHECK_SHLIBS_TOXIC") {
             Ok(s) => {
                 let mut v = vec![];
@@ -391,31 +508,65 @@ impl CheckShlibs {
             }
         };
 
-        let mut state = CheckState {
+        let state = CheckState {
             destdir,
             cross_destdir,
             system_paths,
+            lib_token,
+            lib_unexpanded: AtomicBool::new(false),
             wrkdir,
             wrkref,
             pkg_admin_cmd,
             pkg_admin_args,
             depends,
             toxic,
-            statlibs: HashMap::new(),
-            pkgdb: HashMap::new(),
+            statlibs: Mutex::new(HashMap::new()),
+            symbols: Mutex::new(HashMap::new()),
+            pkgdb: Mutex::new(HashMap::new()),
         };
 
         /*
-         * Ok let's go.
+         * Ok let's go.  Objects are read and checked in parallel, with the
+         * output for each collected and then printed in input order.
          */
-        for line in io::stdin().lock().lines() {
-            let line = line?;
-            let path = Path::new(&line);
-            match fs::read(path) {
-                Ok(dso) => self.check_dso(path, &dso, &mut state)?,
-                Err(e) => eprintln!("{}: {e}", path.display()),
+        let objects: Vec<PathBuf> = io::stdin()
+            .lock()
+            .lines()
+            .map(|l| l.map(PathBuf::from))
+            .collect::<io::Result<_>>()?;
+        let pool = build_thread_pool(self.jobs)?;
+        let results: Vec<anyhow::Result<Result<Vec<String>, String>>> = pool
+            .install(|| {
+                objects
+                    .par_iter()
+                    .map(|path| {
+                        let dso = match fs::read(path) {
+                            Ok(dso) => dso,
+                            Err(e) => {
+                                return Ok(Err(format!(
+                                    "{}: {e}",
+                                    path.display()
+                                )));
+                            }
+                        };
+                        let mut out = vec![];
+                        self.check_dso(path, &dso, &state, &mut out)?;
+                        Ok(Ok(out))
+                    })
+                    .collect()
+            });
+        for result in results {
+            match result? {
+                Ok(out) => out.iter().for_each(|line| println!("{line}")),
+                Err(e) => eprintln!("{e}"),
             }
         }
+        if state.lib_unexpanded.load(Ordering::Relaxed) {
+            eprintln!(
+                "WARNING: $LIB in RPATH or RUNPATH not expanded, \
+                 set CHECK_SHLIBS_LIB"
+            );
+        }
 
         Ok(0)
     }
@@ -438,43 +589,48 @@ mod tests {
             cross_destdir: None,
             destdir: PathBuf::from("/destdir"),
             system_paths: vec![],
+            lib_token: None,
+            lib_unexpanded: AtomicBool::new(false),
             wrkdir: PathBuf::from("/wrkdir"),
             wrkref: vec![PathBuf::from("/wrkref")],
             pkg_admin_cmd: PathBuf::from("/notyet"),
             pkg_admin_args: vec![],
             depends: vec![],
             toxic: vec![re("libtoxic.so"), re("^/toxic")],
-            statlibs: HashMap::new(),
-            pkgdb: HashMap::new(),
+            statlibs: Mutex::new(HashMap::new()),
+            symbols: Mutex::new(HashMap::new()),
+            pkgdb: Mutex::new(HashMap::new()),
         };
+        let mut out = vec![];
 
         let obj = "/opt/pkg/bin/mutt";
         /*
          * Library paths must be absolute.
          */
-        assert!(!check_shlib(obj, "libfoo.so", &state));
+        assert!(!check_shlib(obj, "libfoo.so", &state, &mut out));
         /*
          * Library paths must avoid toxic paths.
          */
-        assert!(!check_shlib(obj, "/libtoxic.so", &state));
-        assert!(!check_shlib(obj, "/toxic/lib.so", &state));
+        assert!(!check_shlib(obj, "/libtoxic.so", &state, &mut out));
+        assert!(!check_shlib(obj, "/toxic/lib.so", &state, &mut out));
         /*
          * Library paths must not start with WRKDIR.
          */
-        assert!(!check_shlib(obj, "/wrkdir/libfoo.so", &state));
+        assert!(!check_shlib(obj, "/wrkdir/libfoo.so", &state, &mut out));
         /*
          * Library paths must not match CHECK_WRKREF_EXTRA_DIRS.
          */
-        assert!(!check_shlib(obj, "/wrkref/libfoo.so", &state));
+        assert!(!check_shlib(obj, "/wrkref/libfoo.so", &state, &mut out));
         /*
          * These should be fine.
          */
-        assert!(check_shlib(obj, "/libfoo.so", &state));
-        assert!(check_shlib(obj, "/libnottoxic.so", &state));
+        assert!(check_shlib(obj, "/libfoo.so", &state, &mut out));
+        assert!(check_shlib(obj, "/libnottoxic.so", &state, &mut out));
 
-        /*
-         * Uncomment this to verify stdout.
-         */
-        //assert!(false);
+        assert_eq!(out.len(), 5);
+        assert_eq!(
+            out[0],
+            "/opt/pkg/bin/mutt: relative library path: libfoo.so"
+        );
     }
 }
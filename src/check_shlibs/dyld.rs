@@ -0,0 +1,339 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Resolve Mach-O load command paths the same way as dyld(1), and split
+ * universal binaries into their architecture slices.  This is kept separate
+ * from the macOS-only checks so that it can be tested anywhere.
+ */
+
+use crate::check_shlibs::normalize_path;
+use goblin::mach::constants::cputype::get_arch_name_from_types;
+use goblin::mach::header::MH_EXECUTE;
+use goblin::mach::{Mach, MachO, SingleArch};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/*
+ * A single architecture of a Mach-O object.  "arch" is only set for slices
+ * of a universal binary, so that thin objects are reported as before.
+ */
+pub struct Slice<'a> {
+    pub arch: Option<String>,
+    pub obj: MachO<'a>,
+}
+
+/*
+ * Parse a Mach-O object into its architecture slices.  Anything that is not
+ * a Mach-O object, and any archives contained in a universal binary, are
+ * ignored.
+ */
+pub fn slices(object: &[u8]) -> Vec<Slice<'_>> {
+    let fat = match Mach::parse(object) {
+        Ok(Mach::Binary(obj)) => return vec![Slice { arch: None, obj }],
+        Ok(Mach::Fat(fat)) => fat,
+        Err(_) => return vec![],
+    };
+    let mut slices = vec![];
+    for arch in &fat {
+        if let Ok(SingleArch::MachO(obj)) = arch {
+            let (cputype, subtype) =
+                (obj.header.cputype(), obj.header.cpusubtype());
+            let arch = match get_arch_name_from_types(cputype, subtype) {
+                Some(name) => name.to_string(),
+                None => format!("cputype {cputype}"),
+            };
+            slices.push(Slice { arch: Some(arch), obj });
+        }
+    }
+    slices
+}
+
+/*
+ * Return the architectures of any slices whose set of libraries differs from
+ * the first slice.  Ordering is ignored, as it does not affect resolution.
+ */
+pub fn differing_slices<'a>(slices: &'a [Slice]) -> Vec<&'a str> {
+    let Some((first, rest)) = slices.split_first() else {
+        return vec![];
+    };
+    let libs: BTreeSet<&str> = first.obj.libs.iter().copied().collect();
+    rest.iter()
+        .filter(|s| s.obj.libs.iter().copied().collect::<BTreeSet<_>>() != libs)
+        .filter_map(|s| s.arch.as_deref())
+        .collect()
+}
+
+/*
+ * Expand a leading @loader_path or @executable_path.  "origin" is the
+ * directory that the object will be installed to.
+ *
+ * @executable_path refers to the main program, which for anything other
+ * than an executable depends on what loads it, so returns None.
+ */
+fn expand_prefix(
+    path: &str,
+    origin: &Path,
+    executable: bool,
+) -> Option<PathBuf> {
+    if let Some(rest) = path.strip_prefix("@loader_path") {
+        return Some(normalize_path(&origin.join(format!(".{rest}"))));
+    }
+    if let Some(rest) = path.strip_prefix("@executable_path") {
+        if !executable {
+            return None;
+        }
+        return Some(normalize_path(&origin.join(format!(".{rest}"))));
+    }
+    Some(PathBuf::from(path))
+}
+
+/*
+ * Return the list of paths that dyld would try, in order, for a library
+ * named by a load command in "obj".  @rpath is substituted with each of the
+ * object's LC_RPATH entries in turn, which may themselves use @loader_path
+ * or @executable_path.
+ *
+ * Returns None if the library cannot be resolved without knowing which
+ * program will load the object.
+ */
+pub fn search_paths(
+    obj: &MachO,
+    lib: &str,
+    origin: &Path,
+) -> Option<Vec<PathBuf>> {
+    let executable = obj.header.filetype == MH_EXECUTE;
+    match lib.strip_prefix("@rpath/") {
+        Some(rest) => Some(
+            obj.rpaths
+                .iter()
+                .filter_map(|rp| expand_prefix(rp, origin, executable))
+                .map(|rp| normalize_path(&rp.join(rest)))
+                .collect(),
+        ),
+        None => Some(vec![expand_prefix(lib, origin, executable)?]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAT_MAGIC: u32 = 0xCAFE_BABE;
+    const MH_MAGIC_64: u32 = 0xFEED_FACF;
+    const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+    const CPU_TYPE_ARM64: u32 = 0x0100_000C;
+    const CPU_SUBTYPE_X86_64_ALL: u32 = 3;
+    const MH_DYLIB: u32 = 6;
+    const LC_LOAD_DYLIB: u32 = 0x0C;
+    const LC_RPATH: u32 = 0x8000_001C;
+
+    fn push_u32(buf: &mut Vec<u8>, v: u32) {
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /*
+     * Build a load command with a single string argument at "offset",
+     * padded to 8-byte alignment.
+     */
+    fn build_str_cmd(cmd: u32, offset: u32, s: &str) -> Vec<u8> {
+        let size = (offset as usize + s.len() + 1).next_multiple_of(8);
+        let mut buf = Vec::with_capacity(size);
+        push_u32(&mut buf, cmd);
+        push_u32(&mut buf, size as u32);
+        push_u32(&mut buf, offset);
+        buf.resize(offset as usize, 0);
+        buf.extend_from_slice(s.as_bytes());
+        buf.resize(size, 0);
+        buf
+    }
+
+    /*
+     * Build a minimal Mach-O 64-bit object with LC_RPATH and LC_LOAD_DYLIB
+     * commands.
+     */
+    fn build_macho(filetype: u32, rpaths: &[&str], deps: &[&str]) -> Vec<u8> {
+        build_macho_arch(
+            CPU_TYPE_X86_64,
+            CPU_SUBTYPE_X86_64_ALL,
+            filetype,
+            rpaths,
+            deps,
+        )
+    }
+
+    fn build_macho_arch(
+        cputype: u32,
+        cpusubtype: u32,
+        filetype: u32,
+        rpaths: &[&str],
+        deps: &[&str],
+    ) -> Vec<u8> {
+        let mut cmds = vec![];
+        for rpath in rpaths {
+            cmds.extend(build_str_cmd(LC_RPATH, 12, rpath));
+        }
+        for dep in deps {
+            cmds.extend(build_str_cmd(LC_LOAD_DYLIB, 24, dep));
+        }
+        let mut macho = vec![];
+        push_u32(&mut macho, MH_MAGIC_64);
+        push_u32(&mut macho, cputype);
+        push_u32(&mut macho, cpusubtype);
+        push_u32(&mut macho, filetype);
+        push_u32(&mut macho, (rpaths.len() + deps.len()) as u32);
+        push_u32(&mut macho, cmds.len() as u32); /* sizeofcmds */
+        push_u32(&mut macho, 0); /* flags */
+        push_u32(&mut macho, 0); /* reserved */
+        macho.extend(cmds);
+        macho
+    }
+
+    /*
+     * Build a universal binary from a list of (cputype, cpusubtype, object).
+     * The fat header is big-endian, and each slice is page aligned.
+     */
+    fn build_fat(arches: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut header = vec![];
+        let mut body = vec![];
+        let mut offset = 0x1000;
+        header.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+        header.extend_from_slice(&(arches.len() as u32).to_be_bytes());
+        for (cputype, cpusubtype, obj) in arches {
+            for v in [*cputype, *cpusubtype, offset, obj.len() as u32, 12] {
+                header.extend_from_slice(&v.to_be_bytes());
+            }
+            body.resize(offset as usize - 0x1000, 0);
+            body.extend_from_slice(obj);
+            offset = (0x1000 + body.len() as u32).next_multiple_of(0x1000);
+        }
+        header.resize(0x1000, 0);
+        header.extend(body);
+        header
+    }
+
+    fn paths(v: &[&str]) -> Option<Vec<PathBuf>> {
+        Some(v.iter().map(PathBuf::from).collect())
+    }
+
+    #[test]
+    fn test_search_paths() -> anyhow::Result<()> {
+        let origin = Path::new("/opt/pkg/bin");
+        let rpaths = [
+            "@loader_path/../lib",
+            "@executable_path/../lib/foo",
+            "/opt/pkg/lib/bar",
+        ];
+        let deps = [
+            "@rpath/libfoo.dylib",
+            "@loader_path/libbar.dylib",
+            "@executable_path/../lib/libbaz.dylib",
+            "/usr/lib/libSystem.B.dylib",
+        ];
+
+        let bytes = build_macho(MH_EXECUTE, &rpaths, &deps);
+        let obj = MachO::parse(&bytes, 0)?;
+        assert_eq!(obj.rpaths, rpaths);
+        assert_eq!(
+            search_paths(&obj, deps[0], origin),
+            paths(&[
+                "/opt/pkg/lib/libfoo.dylib",
+                "/opt/pkg/lib/foo/libfoo.dylib",
+                "/opt/pkg/lib/bar/libfoo.dylib",
+            ])
+        );
+        assert_eq!(
+            search_paths(&obj, deps[1], origin),
+            paths(&["/opt/pkg/bin/libbar.dylib"])
+        );
+        assert_eq!(
+            search_paths(&obj, deps[2], origin),
+            paths(&["/opt/pkg/lib/libbaz.dylib"])
+        );
+        assert_eq!(search_paths(&obj, deps[3], origin), paths(&[deps[3]]));
+
+        /*
+         * In a library, @executable_path depends on the program that loads
+         * it, so is skipped in rpaths and unresolvable as a library name.
+         */
+        let bytes = build_macho(MH_DYLIB, &rpaths, &deps);
+        let obj = MachO::parse(&bytes, 0)?;
+        assert_eq!(
+            search_paths(&obj, deps[0], origin),
+            paths(&[
+                "/opt/pkg/lib/libfoo.dylib",
+                "/opt/pkg/lib/bar/libfoo.dylib",
+            ])
+        );
+        assert_eq!(search_paths(&obj, deps[2], origin), None);
+
+        /*
+         * No LC_RPATH entries means there is nowhere to look for @rpath.
+         */
+        let bytes = build_macho(MH_EXECUTE, &[], &deps);
+        let obj = MachO::parse(&bytes, 0)?;
+        assert_eq!(search_paths(&obj, deps[0], origin), paths(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_slices() -> anyhow::Result<()> {
+        let libs = ["/usr/lib/libSystem.B.dylib", "/opt/pkg/lib/libfoo.dylib"];
+        let thin = build_macho(MH_EXECUTE, &[], &libs);
+        let s = slices(&thin);
+        assert_eq!(s.len(), 1);
+        assert_eq!(s[0].arch, None);
+        assert!(differing_slices(&s).is_empty());
+
+        /*
+         * Load command order does not matter, but a missing library does.
+         */
+        let reordered = [libs[1], libs[0]];
+        let fat = build_fat(&[
+            (CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL, thin.clone()),
+            (
+                CPU_TYPE_ARM64,
+                0,
+                build_macho_arch(
+                    CPU_TYPE_ARM64,
+                    0,
+                    MH_EXECUTE,
+                    &[],
+                    &reordered,
+                ),
+            ),
+            (
+                CPU_TYPE_ARM64,
+                2,
+                build_macho_arch(
+                    CPU_TYPE_ARM64,
+                    2,
+                    MH_EXECUTE,
+                    &[],
+                    &libs[..1],
+                ),
+            ),
+        ]);
+        let s = slices(&fat);
+        let arches: Vec<_> = s.iter().map(|s| s.arch.as_deref()).collect();
+        assert_eq!(arches, [Some("x86_64"), Some("arm64"), Some("arm64e")]);
+        assert_eq!(s[2].obj.libs, ["self", libs[0]]);
+        assert_eq!(differing_slices(&s), ["arm64e"]);
+
+        assert!(slices(b"not a mach-o object").is_empty());
+        Ok(())
+    }
+}
@@ -15,28 +15,130 @@
  */
 
 use crate::check_shlibs::{CheckShlibs, CheckState};
-use crate::check_shlibs::{check_pkg, check_shlib};
+use crate::check_shlibs::{check_pkg, check_shlib, normalize_path, pkg_owner};
 use goblin::elf::Elf;
+use goblin::elf::header;
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{STB_GLOBAL, STB_LOCAL, STB_WEAK};
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+/*
+ * Value of $PLATFORM for a machine type, matching what ld.so uses.  Returns
+ * None for machines that we don't know about.
+ */
+fn platform(machine: u16) -> Option<&'static str> {
+    match machine {
+        header::EM_X86_64 => Some("x86_64"),
+        header::EM_386 if cfg!(target_os = "linux") => Some("i686"),
+        header::EM_386 => Some("i386"),
+        header::EM_AARCH64 => Some("aarch64"),
+        header::EM_PPC64 => Some("ppc64"),
+        header::EM_PPC => Some("ppc"),
+        header::EM_SPARCV9 => Some("sparc64"),
+        header::EM_RISCV => Some("riscv"),
+        _ => None,
+    }
+}
+
+/*
+ * Expand the dynamic string tokens $ORIGIN, $LIB and $PLATFORM (or their
+ * ${TOKEN} forms) in an RPATH or RUNPATH entry, in the same way as ld.so.
+ *
+ * "origin" is the directory that the object will be installed to.  What $LIB
+ * expands to depends on how ld.so was configured, for example "lib64" on Red
+ * Hat but "lib/x86_64-linux-gnu" on Debian, and cannot be derived from the
+ * object itself.  It is therefore only expanded if "lib" is set (from
+ * CHECK_SHLIBS_LIB), and otherwise left as-is so that the entry matches
+ * nothing.  Returns None if the entry contains a token that cannot be
+ * expanded, as ld.so ignores such entries.  Expanded paths are normalised so
+ * that "$ORIGIN/../lib" results in a path that can be looked up in the
+ * package database.
+ */
+fn expand_dst(
+    rpath: &str,
+    origin: &Path,
+    lib: Option<&str>,
+    platform: Option<&str>,
+) -> Option<String> {
+    if !rpath.contains('$') {
+        return Some(rpath.to_string());
+    }
+    let mut expanded = String::new();
+    let mut rest = rpath;
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        let (name, len) = match rest.strip_prefix('{') {
+            Some(r) => {
+                let end = r.find('}')?;
+                (&r[..end], end + 2)
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (&rest[..end], end)
+            }
+        };
+        match name {
+            "ORIGIN" => expanded.push_str(&origin.to_string_lossy()),
+            "LIB" => match lib {
+                Some(lib) => expanded.push_str(lib),
+                None => {
+                    expanded.push('$');
+                    expanded.push_str(&rest[..len]);
+                }
+            },
+            "PLATFORM" => expanded.push_str(platform?),
+            _ => return None,
+        }
+        rest = &rest[len..];
+    }
+    expanded.push_str(rest);
+
+    let path = normalize_path(Path::new(&expanded));
+    Some(path.to_string_lossy().into_owned())
+}
 
 impl CheckShlibs {
     pub fn check_dso(
         &self,
         path: &Path,
         object: &[u8],
-        state: &mut CheckState,
+        state: &CheckState,
+        out: &mut Vec<String>,
     ) -> anyhow::Result<()> {
         let elf = match Elf::parse(object) {
             Ok(o) => o,
             Err(_) => return Ok(()),
         };
 
-        let mut rpaths: Vec<&str> = vec![];
-        if let Some(p) = elf.runpaths.first() {
-            rpaths.extend(p.split(':'));
-        }
-        if let Some(p) = elf.rpaths.first() {
-            rpaths.extend(p.split(':'));
+        /*
+         * $ORIGIN is the directory the object will be installed to, not where
+         * it currently is inside DESTDIR.
+         */
+        let origin = state.install_dir(path);
+        let libdir = state.lib_token.as_deref();
+        let platform = platform(elf.header.e_machine);
+
+        let mut rpaths: Vec<String> = vec![];
+        for p in elf.runpaths.first().iter().chain(elf.rpaths.first().iter()) {
+            for r in p.split(':') {
+                let Some(rpath) = expand_dst(r, &origin, libdir, platform)
+                else {
+                    continue;
+                };
+                if libdir.is_none()
+                    && (r.contains("$LIB") || r.contains("${LIB}"))
+                {
+                    state.lib_unexpanded.store(true, Ordering::Relaxed);
+                }
+                rpaths.push(rpath);
+            }
         }
 
         /*
@@ -44,7 +146,9 @@ impl CheckShlibs {
          * to search for them.  Search in a specific order, and only run checks
          * where appropriate.
          */
-        'nextlib: for lib in elf.libraries {
+        let mut resolved: Vec<(&str, PathBuf)> = vec![];
+        let mut missing = false;
+        'nextlib: for &lib in &elf.libraries {
             /*
              * Look inside DESTDIR for any RUNPATH entries that haven't been
              * installed yet.  All we can do is check for existence, as they
@@ -52,7 +156,7 @@ impl CheckShlibs {
              * come first, otherwise check_pkg will fail when a library that
              * belongs to this package is found to be installed.
              */
-            for &rpath in &rpaths {
+            for rpath in &rpaths {
                 let mut libpath = state.destdir.clone();
                 let rp = PathBuf::from(rpath);
                 match rp.strip_prefix("/") {
@@ -60,15 +164,8 @@ impl CheckShlibs {
                     Err(_) => libpath.push(rp),
                 }
                 libpath.push(lib);
-                let exists = match state.statlibs.get(&libpath) {
-                    Some(e) => *e,
-                    None => {
-                        let e = libpath.exists();
-                        state.statlibs.insert(libpath.to_path_buf(), e);
-                        e
-                    }
-                };
-                if exists {
+                if state.lib_exists(&libpath) {
+                    resolved.push((lib, libpath));
                     continue 'nextlib;
                 }
             }
@@ -76,7 +173,8 @@ impl CheckShlibs {
             /*
              * RUNPATH entries.  Add CROSS_DESTDIR prefix if set.
              */
-            for &rpath in &rpaths {
+            for rpath in &rpaths {
+                let rpath = rpath.as_str();
                 let mut libpath: PathBuf;
                 match &state.cross_destdir {
                     Some(crossdir) => {
@@ -89,17 +187,10 @@ impl CheckShlibs {
                     None => libpath = PathBuf::from(rpath),
                 };
                 libpath.push(lib);
-                let exists = match state.statlibs.get(&libpath) {
-                    Some(e) => *e,
-                    None => {
-                        let e = libpath.exists();
-                        state.statlibs.insert(libpath.to_path_buf(), e);
-                        e
-                    }
-                };
-                if exists {
-                    check_shlib(path, &libpath, state);
-                    check_pkg(path, &libpath, state)?;
+                if state.lib_exists(&libpath) {
+                    check_shlib(path, &libpath, state, out);
+                    check_pkg(path, &libpath, state, out)?;
+                    resolved.push((lib, libpath));
                     continue 'nextlib;
                 }
             }
@@ -111,16 +202,9 @@ impl CheckShlibs {
             for rpath in &state.system_paths {
                 let mut libpath = PathBuf::from(rpath);
                 libpath.push(lib);
-                let exists = match state.statlibs.get(&libpath) {
-                    Some(e) => *e,
-                    None => {
-                        let e = libpath.exists();
-                        state.statlibs.insert(libpath.to_path_buf(), e);
-                        e
-                    }
-                };
-                if exists {
-                    check_shlib(path, &libpath, state);
+                if state.lib_exists(&libpath) {
+                    check_shlib(path, &libpath, state, out);
+                    resolved.push((lib, libpath));
                     continue 'nextlib;
                 }
             }
@@ -128,8 +212,227 @@ impl CheckShlibs {
             /*
              * If we're still here the library was not found.
              */
-            println!("{}: missing library: {}", path.display(), lib);
+            out.push(format!("{}: missing library: {}", path.display(), lib));
+            missing = true;
+        }
+
+        /*
+         * Symbols can only be checked if every library was found.
+         */
+        if (self.symbols || self.unused) && !missing {
+            self.check_symbols(path, &elf, &resolved, state, out)?;
         }
         Ok(())
     }
 }
+
+/*
+ * Return the dynamic symbols defined by an object, as "name" and, for
+ * versioned symbols, "name@version".  Hidden (non-default) versions are
+ * included as they can still be bound to by version.
+ */
+fn defined_symbols(elf: &Elf) -> HashSet<String> {
+    let mut versions: HashMap<u16, &str> = HashMap::new();
+    if let Some(verdef) = &elf.verdef {
+        for vd in verdef.iter() {
+            if let Some(name) = vd
+                .iter()
+                .next()
+                .and_then(|aux| elf.dynstrtab.get_at(aux.vda_name))
+            {
+                versions.insert(vd.vd_ndx, name);
+            }
+        }
+    }
+
+    let mut syms = HashSet::new();
+    for (i, sym) in elf.dynsyms.iter().enumerate() {
+        if sym.st_shndx == SHN_UNDEF as usize || sym.st_bind() == STB_LOCAL {
+            continue;
+        }
+        let Some(name) = elf.dynstrtab.get_at(sym.st_name) else {
+            continue;
+        };
+        if name.is_empty() {
+            continue;
+        }
+        syms.insert(name.to_string());
+        if let Some(ver) = symbol_version(elf, i).and_then(|v| versions.get(&v))
+        {
+            syms.insert(format!("{name}@{ver}"));
+        }
+    }
+    syms
+}
+
+/*
+ * Return the version index of a dynamic symbol, if it has a version other
+ * than local or global.
+ */
+fn symbol_version(elf: &Elf, idx: usize) -> Option<u16> {
+    let v = elf.versym.as_ref()?.get_at(idx)?.version();
+    if v > 1 { Some(v) } else { None }
+}
+
+/*
+ * Return the symbols defined by a library, using the cache if it has already
+ * been loaded by another object.
+ */
+fn library_symbols(
+    path: &Path,
+    state: &CheckState,
+) -> Option<Arc<HashSet<String>>> {
+    if let Ok(symbols) = state.symbols.lock() {
+        if let Some(syms) = symbols.get(path) {
+            return syms.clone();
+        }
+    }
+    let syms = fs::read(path).ok().and_then(|bytes| {
+        Elf::parse(&bytes).ok().map(|lib| Arc::new(defined_symbols(&lib)))
+    });
+    if let Ok(mut symbols) = state.symbols.lock() {
+        symbols.insert(path.to_path_buf(), syms.clone());
+    }
+    syms
+}
+
+impl CheckShlibs {
+    /*
+     * Bind each undefined symbol in an object to the library that provides
+     * it, in the same way as ld.so: versioned references from .gnu.version_r
+     * bind to the library named in the reference, anything else to the first
+     * library in DT_NEEDED order that defines it.
+     *
+     * With --symbols, report any non-weak symbols that no library provides.
+     * Only direct dependencies are searched, so symbols that a library
+     * expects to be provided by the program loading it (for example,
+     * plugins) will be reported.
+     *
+     * With --unused, report any libraries that no symbol was bound to, along
+     * with the package that owns them.
+     *
+     * If any library cannot be parsed then nothing is reported.
+     */
+    fn check_symbols(
+        &self,
+        path: &Path,
+        elf: &Elf,
+        resolved: &[(&str, PathBuf)],
+        state: &CheckState,
+        out: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let mut libs: Vec<(&str, Arc<HashSet<String>>)> = vec![];
+        for (lib, libpath) in resolved {
+            match library_symbols(libpath, state) {
+                Some(syms) => libs.push((lib, syms)),
+                None => return Ok(()),
+            }
+        }
+
+        let mut needed: HashMap<u16, (&str, &str)> = HashMap::new();
+        if let Some(verneed) = &elf.verneed {
+            for vn in verneed.iter() {
+                let Some(file) = elf.dynstrtab.get_at(vn.vn_file) else {
+                    continue;
+                };
+                for aux in vn.iter() {
+                    if let Some(ver) = elf.dynstrtab.get_at(aux.vna_name) {
+                        needed.insert(aux.vna_other, (file, ver));
+                    }
+                }
+            }
+        }
+
+        let mut used = vec![false; libs.len()];
+        for (i, sym) in elf.dynsyms.iter().enumerate() {
+            let bind = sym.st_bind();
+            if sym.st_shndx != SHN_UNDEF as usize
+                || (bind != STB_GLOBAL && bind != STB_WEAK)
+            {
+                continue;
+            }
+            let Some(name) = elf.dynstrtab.get_at(sym.st_name) else {
+                continue;
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let (want, file) =
+                match symbol_version(elf, i).and_then(|v| needed.get(&v)) {
+                    Some((file, ver)) => (format!("{name}@{ver}"), Some(*file)),
+                    None => (name.to_string(), None),
+                };
+            /*
+             * Fall back to searching every library if a versioned reference
+             * names a library that isn't a direct dependency.
+             */
+            let direct = libs.iter().any(|(lib, _)| Some(*lib) == file);
+            let provider = libs.iter().position(|(lib, syms)| {
+                (!direct || Some(*lib) == file) && syms.contains(&want)
+            });
+            match provider {
+                Some(idx) => used[idx] = true,
+                None if self.symbols && bind == STB_GLOBAL => {
+                    out.push(format!(
+                        "{}: undefined symbol: {}",
+                        path.display(),
+                        want
+                    ));
+                }
+                None => {}
+            }
+        }
+
+        if self.unused {
+            for ((lib, libpath), used) in resolved.iter().zip(&used) {
+                if *used {
+                    continue;
+                }
+                match pkg_owner(libpath, state)? {
+                    Some(pkg) => out.push(format!(
+                        "{}: unused library: {} ({})",
+                        path.display(),
+                        lib,
+                        pkg
+                    )),
+                    None => out.push(format!(
+                        "{}: unused library: {}",
+                        path.display(),
+                        lib
+                    )),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_dst() {
+        let origin = Path::new("/opt/pkg/bin");
+        let expand = |r| expand_dst(r, origin, Some("lib64"), Some("x86_64"));
+        assert_eq!(expand("/usr/lib"), Some("/usr/lib".to_string()));
+        assert_eq!(expand("$ORIGIN"), Some("/opt/pkg/bin".to_string()));
+        assert_eq!(expand("$ORIGIN/../lib"), Some("/opt/pkg/lib".to_string()));
+        assert_eq!(
+            expand("${ORIGIN}/./lib"),
+            Some("/opt/pkg/bin/lib".to_string())
+        );
+        assert_eq!(expand("/usr/$LIB"), Some("/usr/lib64".to_string()));
+        assert_eq!(
+            expand("$ORIGIN/../${LIB}/$PLATFORM"),
+            Some("/opt/pkg/lib64/x86_64".to_string())
+        );
+        assert_eq!(expand("/usr/$LIBX"), None);
+        assert_eq!(expand("/usr/${LIB"), None);
+        assert_eq!(expand_dst("/usr/$PLATFORM", origin, None, None), None);
+        assert_eq!(
+            expand_dst("$ORIGIN/../${LIB}", origin, None, None),
+            Some("/opt/pkg/${LIB}".to_string())
+        );
+    }
+}
@@ -14,45 +14,66 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+use crate::check_shlibs::dyld::{
+    Slice, differing_slices, search_paths, slices,
+};
 use crate::check_shlibs::{CheckShlibs, CheckState};
 use crate::check_shlibs::{check_pkg, check_shlib};
-use goblin::mach::{Mach, SingleArch};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 impl CheckShlibs {
     pub fn check_dso(
         &self,
         path: &Path,
         object: &[u8],
-        state: &mut CheckState,
+        state: &CheckState,
+        out: &mut Vec<String>,
     ) -> anyhow::Result<()> {
-        let pobj = match Mach::parse(object) {
-            Ok(o) => o,
-            Err(_) => return Ok(()),
-        };
-        let obj = match pobj {
-            /*
-             * XXX: Support Universal binaries correctly.  It's unlikely we'll
-             * encounter these in pkgsrc at present as there's no multiarch
-             * support.
-             */
-            Mach::Fat(fat) => {
-                if let Ok(SingleArch::MachO(o)) = fat.get(0) {
-                    o
-                } else {
-                    return Ok(());
-                }
-            }
-            Mach::Binary(bin) => bin,
-        };
+        let slices = slices(object);
+
         /*
-         * For shared libraries, libs[0] is the LC_ID_DYLIB install name
-         * which we want to verify.  For executables there is no LC_ID_DYLIB
-         * (obj.name is None) and goblin places a placeholder at libs[0]
-         * that should be skipped.
+         * Slices of a universal binary should all link against the same
+         * libraries, anything else is likely to be a build issue.
          */
+        if let Some(first) = slices.first().and_then(|s| s.arch.as_deref()) {
+            for arch in differing_slices(&slices) {
+                out.push(format!(
+                    "{}: {} dependencies differ from {}",
+                    path.display(),
+                    arch,
+                    first
+                ));
+            }
+        }
+
+        let origin = state.install_dir(path);
+        for slice in &slices {
+            self.check_slice(path, slice, &origin, state, out)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Check a single architecture.  Findings for slices of a universal binary
+     * are reported with the architecture name after the path.
+     */
+    fn check_slice(
+        &self,
+        path: &Path,
+        slice: &Slice,
+        origin: &Path,
+        state: &CheckState,
+        out: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let obj = &slice.obj;
+        let label = match &slice.arch {
+            Some(arch) => PathBuf::from(format!("{} ({arch})", path.display())),
+            None => path.to_path_buf(),
+        };
+        let path = label.as_path();
+
         let skip = usize::from(obj.name.is_none());
-        for lib in obj.libs.into_iter().skip(skip) {
+        'nextlib: for &lib in obj.libs.iter().skip(skip) {
             /*
              * Skip system libraries if requested on newer macOS.  Apple no
              * longer ship the actual file system entries (because lol) so any
@@ -65,50 +86,46 @@ impl CheckShlibs {
                 continue;
             }
 
+            /*
+             * Resolve @rpath, @loader_path and @executable_path into the
+             * list of paths that dyld will try.  If this depends on the
+             * program that loads us then there is nothing we can check.
+             */
+            let Some(libpaths) = search_paths(obj, lib, origin) else {
+                continue;
+            };
+
             /*
              * Look inside DESTDIR for any paths that haven't been installed
              * yet.  If found we're done, as we can't run any additional checks
              * on it.
              */
-            let mut libpath = state.destdir.clone();
-            match lib.strip_prefix("/") {
-                Some(p) => libpath.push(p),
-                None => libpath.push(lib),
-            }
-            let exists = match state.statlibs.get(&libpath) {
-                Some(e) => *e,
-                None => {
-                    let e = libpath.exists();
-                    state.statlibs.insert(libpath.to_path_buf(), e);
-                    e
+            for libpath in &libpaths {
+                let mut destpath = state.destdir.clone();
+                match libpath.strip_prefix("/") {
+                    Ok(p) => destpath.push(p),
+                    Err(_) => destpath.push(libpath),
+                }
+                if state.lib_exists(&destpath) {
+                    continue 'nextlib;
                 }
-            };
-            if exists {
-                continue;
             }
 
             /*
-             * Check direct path.  If found run all checks.
+             * Check direct paths.  If found run all checks.
              */
-            let libpath = Path::new(lib);
-            let exists = match state.statlibs.get(libpath) {
-                Some(e) => *e,
-                None => {
-                    let e = libpath.exists();
-                    state.statlibs.insert(libpath.to_path_buf(), e);
-                    e
+            for libpath in &libpaths {
+                if state.lib_exists(libpath) {
+                    check_shlib(path, libpath, state, out);
+                    check_pkg(path, libpath, state, out)?;
+                    continue 'nextlib;
                 }
-            };
-            if exists {
-                check_shlib(path, libpath, state);
-                check_pkg(path, libpath, state)?;
-                continue;
             }
 
             /*
              * If we're still here the library was not found.
              */
-            println!("{}: missing library: {}", path.display(), lib);
+            out.push(format!("{}: missing library: {}", path.display(), lib));
         }
         Ok(())
     }
@@ -15,22 +15,38 @@
  */
 
 use crate::build_thread_pool;
-use clap::Args;
-use pkgsrc::digest::Digest;
-use pkgsrc::distinfo::{Distinfo, DistinfoError, Entry};
+use crate::cache::HashCache;
+use crate::distinfo_file::{Distinfo, DistinfoError, Entry};
+use crate::hasher::{Algorithm, entry_digests, verify_checksums};
+use clap::{ArgGroup, Args, ValueEnum};
+use pkgsrc::distinfo::EntryType;
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{self, BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 #[derive(Args, Debug)]
+#[command(group = ArgGroup::new("alldirs").multiple(true))]
 pub struct CheckSum {
     #[arg(short = 'a', value_name = "algorithm")]
     #[arg(help = "Only verify checksums for the specified algorithm")]
     algorithm: Option<String>,
 
+    #[arg(long, requires = "alldirs", conflicts_with_all = ["input", "files"])]
+    #[arg(help = "Verify every entry in distinfo against -d and -P")]
+    all: bool,
+
+    #[arg(short = 'd', value_name = "distdir", group = "alldirs")]
+    #[arg(requires = "all")]
+    #[arg(help = "Directory under which distfiles are found (with --all)")]
+    distdir: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    #[arg(help = "Output format for verification results")]
+    format: OutputFormat,
+
     #[arg(short = 'I', value_name = "input")]
     #[arg(help = "Read files from input instead of command line arguments")]
     input: Option<PathBuf>,
@@ -39,10 +55,19 @@ pub struct CheckSum {
     #[arg(help = "Maximum number of threads (or \"MKTOOL_JOBS\" env var)")]
     jobs: Option<usize>,
 
+    #[arg(short = 'k', long = "keep-going")]
+    #[arg(help = "Report all checksum mismatches instead of the first")]
+    keep_going: bool,
+
     #[arg(short = 'p', default_value = "false")]
     #[arg(help = "Operate in patch mode")]
     patchmode: bool,
 
+    #[arg(short = 'P', value_name = "patchdir", group = "alldirs")]
+    #[arg(requires = "all")]
+    #[arg(help = "Directory under which patches are found (with --all)")]
+    patchdir: Option<PathBuf>,
+
     #[arg(short = 's', value_name = "suffix")]
     #[arg(help = "Strip the specified suffix from file names")]
     stripsuffix: Option<String>,
@@ -56,11 +81,89 @@ pub struct CheckSum {
     files: Vec<PathBuf>,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    /// checksum.awk compatible output
+    Text,
+    /// One JSON object per line
+    Json,
+    /// Test Anything Protocol
+    Tap,
+}
+
 #[derive(Debug)]
 struct CheckResult {
     path: PathBuf,
     entry: Entry,
-    results: Vec<Result<Digest, DistinfoError>>,
+    results: Vec<Result<Algorithm, DistinfoError>>,
+}
+
+/*
+ * Final status of each file (and algorithm, where applicable).
+ */
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok,
+    Mismatch,
+    MissingChecksum,
+    Unrecorded,
+    Missing,
+    IoError,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Mismatch => "mismatch",
+            Status::MissingChecksum => "missing-checksum",
+            Status::Unrecorded => "unrecorded",
+            Status::Missing => "missing",
+            Status::IoError => "io-error",
+        }
+    }
+
+    fn exit_status(&self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::MissingChecksum | Status::Unrecorded => 2,
+            Status::Mismatch | Status::Missing | Status::IoError => 1,
+        }
+    }
+}
+
+/*
+ * A single verification result.  filename is the name as recorded in
+ * distinfo (or as supplied, if unrecorded), while path is the file that was
+ * actually opened, and is only set for I/O errors.
+ */
+#[derive(Debug)]
+struct Record {
+    status: Status,
+    filename: PathBuf,
+    path: Option<PathBuf>,
+    digest: Option<Algorithm>,
+    expected: Option<String>,
+    actual: Option<String>,
+    error: Option<String>,
+}
+
+impl Record {
+    fn new(
+        status: Status,
+        filename: &Path,
+        digest: Option<Algorithm>,
+    ) -> Record {
+        Record {
+            status,
+            filename: filename.to_path_buf(),
+            path: None,
+            digest,
+            expected: None,
+            actual: None,
+            error: None,
+        }
+    }
 }
 
 impl CheckSum {
@@ -134,7 +237,7 @@ impl CheckSum {
         /*
          * No input files, return early.
          */
-        if inputfiles.is_empty() {
+        if inputfiles.is_empty() && !self.all {
             return Ok(0);
         }
 
@@ -155,12 +258,52 @@ impl CheckSum {
             Err(_) => true,
         });
 
+        /*
+         * In --all mode the list of files to check comes from distinfo
+         * itself, and is compared against what is found on disk.  Files
+         * that are recorded but do not exist are reported as missing, using
+         * their distinfo name as with every other status, and files that
+         * exist but are not recorded are added to the same list as unknown
+         * input files.
+         */
+        let mut missingfiles: Vec<PathBuf> = vec![];
+        let mut unrecorded: Vec<PathBuf> = vec![];
+        if self.all {
+            let mut entries: Vec<(&Path, &Entry)> = vec![];
+            if let Some(dir) = &self.distdir {
+                entries.extend(
+                    distinfo
+                        .distfiles()
+                        .into_iter()
+                        .map(|e| (dir.as_path(), e)),
+                );
+                unrecorded.extend(unrecorded_distfiles(dir, &distinfo)?);
+            }
+            if let Some(dir) = &self.patchdir {
+                entries.extend(
+                    distinfo
+                        .patchfiles()
+                        .into_iter()
+                        .map(|e| (dir.as_path(), e)),
+                );
+                unrecorded.extend(unrecorded_patchfiles(dir, &distinfo)?);
+            }
+            for (dir, entry) in entries {
+                let path = dir.join(&entry.filename);
+                if path.exists() {
+                    checkfiles.push((path, entry.clone()));
+                } else {
+                    missingfiles.push(entry.filename.clone());
+                }
+            }
+        }
+
         /*
          * If a single algorithm is requested then only match it.
          */
-        let mut single_digest: Option<Digest> = None;
+        let mut single_digest: Option<Algorithm> = None;
         if let Some(a) = &self.algorithm {
-            single_digest = Some(Digest::from_str(a)?);
+            single_digest = Some(Algorithm::from_str(a)?);
         }
 
         /*
@@ -177,81 +320,269 @@ impl CheckSum {
          * Process checkfiles vec in parallel, storing each result back into
          * its own entry.
          */
+        let cache = HashCache::from_env();
         pool.install(|| {
             checkfiles.par_iter_mut().for_each(|file| {
-                match single_digest {
-                    Some(digest) => {
-                        file.results =
-                            vec![file.entry.verify_checksum(&file.path, digest)]
-                    }
-                    None => {
-                        file.results = file.entry.verify_checksums(&file.path)
-                    }
+                let digests = match single_digest {
+                    Some(digest) => vec![digest],
+                    None => entry_digests(&file.entry),
                 };
+                file.results = verify_checksums(
+                    &file.entry,
+                    &file.path,
+                    &digests,
+                    cache.as_ref(),
+                );
             });
         });
+        if let Some(cache) = &cache {
+            cache.save_or_warn();
+        }
 
         /*
-         * We have processed everything, print results and return compatible
-         * exit status.  Output and order should match checksum.awk.
+         * We have processed everything.  Flatten the results into a list of
+         * records in the order they are to be reported, which should match
+         * checksum.awk: distinfo order, with errors last.
          */
         checkfiles.sort_by(|a, b| a.entry.filename.cmp(&b.entry.filename));
-        let mut rv = 0;
+        let mut records: Vec<Record> = vec![];
         for file in checkfiles {
-            for result in file.results {
+            /*
+             * Results are returned in the same order as the checksums (or
+             * the single digest) so that I/O errors, which don't carry the
+             * digest, can still be associated with one.
+             */
+            let digests = match single_digest {
+                Some(d) => vec![d],
+                None => entry_digests(&file.entry),
+            };
+            for (result, digest) in file.results.into_iter().zip(digests) {
+                let mut record =
+                    Record::new(Status::Ok, &file.entry.filename, Some(digest));
                 match result {
-                    Ok(digest) => println!(
-                        "=> Checksum {} OK for {}",
-                        digest,
-                        file.entry.filename.display()
-                    ),
-                    Err(DistinfoError::Checksum(path, digest, _, _)) => {
-                        eprintln!(
-                            "checksum: Checksum {} mismatch for {}",
-                            digest,
-                            path.display()
-                        );
-                        /* checksum.awk bails on first mismatch */
-                        return Ok(1);
+                    Ok(_) => {}
+                    Err(DistinfoError::Checksum(_, _, exp, got)) => {
+                        record.status = Status::Mismatch;
+                        record.expected = Some(exp);
+                        record.actual = Some(got);
                     }
-                    Err(DistinfoError::MissingChecksum(_, digest)) => {
-                        eprintln!(
-                            "checksum: No {} checksum recorded for {}",
-                            digest,
-                            file.entry.filename.display()
-                        );
-                        rv = 2;
+                    Err(DistinfoError::MissingChecksum(_, _)) => {
+                        record.status = Status::MissingChecksum;
                     }
                     Err(e) => {
-                        eprintln!("checksum: {}: {e}", file.path.display());
-                        rv = 1;
+                        record.status = Status::IoError;
+                        record.path = Some(file.path.clone());
+                        record.error = Some(e.to_string());
                     }
                 }
+                records.push(record);
             }
         }
+
         /*
          * checksum.awk prints missing files in arbitrary order.  We differ
          * in behaviour here and ensure they are sorted, mainly because it
          * ensures test results are stable.
          */
         let mut missing: Vec<PathBuf> = inputfiles.into_keys().collect();
+        missing.extend(unrecorded);
         missing.sort();
         for file in missing {
-            if let Some(digest) = single_digest {
-                eprintln!(
-                    "checksum: No {} checksum recorded for {}",
-                    digest,
-                    file.display()
-                );
-            } else {
+            records.push(Record::new(Status::Unrecorded, &file, single_digest));
+        }
+
+        /*
+         * Files recorded in distinfo that could not be found (--all only).
+         */
+        missingfiles.sort();
+        for file in missingfiles {
+            records.push(Record::new(Status::Missing, &file, None));
+        }
+
+        match self.format {
+            OutputFormat::Text => {
+                print_text(&records, self.keep_going || self.all)
+            }
+            OutputFormat::Json => print_json(&records)?,
+            OutputFormat::Tap => print_tap(&records),
+        }
+
+        /*
+         * The exit status is aggregated: 1 if there were any mismatches or
+         * errors, otherwise 2 if there were any files or algorithms without
+         * a recorded checksum.  This is compatible with checksum.awk, which
+         * also exits 1 on the first mismatch.
+         */
+        let statuses: HashSet<i32> =
+            records.iter().map(|r| r.status.exit_status()).collect();
+        let rv = [1, 2].into_iter().find(|s| statuses.contains(s)).unwrap_or(0);
+        Ok(rv)
+    }
+}
+
+/*
+ * Print results in a format compatible with checksum.awk, successes to
+ * stdout and everything else to stderr.
+ */
+fn print_text(records: &[Record], keep_going: bool) {
+    for r in records {
+        let filename = r.filename.display();
+        match (&r.status, r.digest) {
+            (Status::Ok, Some(d)) => {
+                println!("=> Checksum {d} OK for {filename}")
+            }
+            (Status::Mismatch, Some(d)) => {
+                eprintln!("checksum: Checksum {d} mismatch for {filename}");
+                /*
+                 * checksum.awk bails on first mismatch.  With -k (or --all,
+                 * which is intended to report everything in one sweep) we
+                 * instead carry on, and as there is no compatibility to
+                 * maintain, show the hashes.
+                 */
+                if !keep_going {
+                    return;
+                }
+                if let (Some(exp), Some(got)) = (&r.expected, &r.actual) {
+                    eprintln!("    expected: {exp}");
+                    eprintln!("    actual:   {got}");
+                }
+            }
+            (Status::MissingChecksum | Status::Unrecorded, Some(d)) => {
+                eprintln!("checksum: No {d} checksum recorded for {filename}")
+            }
+            (Status::Unrecorded, None) => {
+                eprintln!("checksum: No checksum recorded for {filename}")
+            }
+            (Status::Missing, _) => {
+                eprintln!("checksum: Missing file {filename}")
+            }
+            (_, _) => {
+                let path = r.path.as_ref().unwrap_or(&r.filename);
                 eprintln!(
-                    "checksum: No checksum recorded for {}",
-                    file.display()
+                    "checksum: {}: {}",
+                    path.display(),
+                    r.error.as_deref().unwrap_or("unknown error")
                 );
             }
-            rv = 2;
         }
+    }
+}
 
-        Ok(rv)
+/*
+ * Print one JSON object per line for each result.
+ */
+fn print_json(records: &[Record]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    for r in records {
+        let obj = serde_json::json!({
+            "filename": r.filename.to_string_lossy(),
+            "algorithm": r.digest.map(|d| d.to_string()),
+            "status": r.status.as_str(),
+            "expected": r.expected,
+            "actual": r.actual,
+            "error": r.error,
+        });
+        writeln!(stdout, "{obj}")?;
+    }
+    stdout.flush()
+}
+
+/*
+ * Print results as a Test Anything Protocol stream, with any additional
+ * information for failures included as YAML diagnostics.
+ */
+fn print_tap(records: &[Record]) {
+    println!("TAP version 13");
+    println!("1..{}", records.len());
+    for (i, r) in records.iter().enumerate() {
+        let ok = if r.status == Status::Ok { "ok" } else { "not ok" };
+        match r.digest {
+            Some(d) => {
+                println!("{ok} {} - {d} {}", i + 1, r.filename.display())
+            }
+            None => println!("{ok} {} - {}", i + 1, r.filename.display()),
+        }
+        if r.status == Status::Ok {
+            continue;
+        }
+        println!("  ---");
+        println!("  status: {}", r.status.as_str());
+        if let Some(exp) = &r.expected {
+            println!("  expected: {exp}");
+        }
+        if let Some(got) = &r.actual {
+            println!("  actual: {got}");
+        }
+        if let Some(err) = &r.error {
+            println!("  error: {}", serde_json::Value::from(err.as_str()));
+        }
+        println!("  ...");
+    }
+}
+
+/*
+ * Return any files found in DIST_SUBDIR directories under distdir that are
+ * not recorded in distinfo.  The top level of DISTDIR is shared between all
+ * packages and so is not checked, but a DIST_SUBDIR is specific to this
+ * package and anything else found there is likely stale.
+ */
+fn unrecorded_distfiles(
+    distdir: &Path,
+    distinfo: &Distinfo,
+) -> io::Result<Vec<PathBuf>> {
+    let mut subdirs: HashSet<&Path> = HashSet::new();
+    for entry in distinfo.distfiles() {
+        if let Some(dir) = entry.filename.parent() {
+            if dir.as_os_str() != "" {
+                subdirs.insert(dir);
+            }
+        }
+    }
+    let mut files = vec![];
+    for subdir in subdirs {
+        let dir = distdir.join(subdir);
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&dir)? {
+            let file = file?;
+            if !file.file_type()?.is_file() {
+                continue;
+            }
+            let filename = subdir.join(file.file_name());
+            if EntryType::from(&filename) == EntryType::Distfile
+                && distinfo.distfile(&filename).is_none()
+            {
+                files.push(filename);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/*
+ * Return any valid patch files found in patchdir that are not recorded in
+ * distinfo, i.e. those that "make makepatchsum" has not been run for.
+ */
+fn unrecorded_patchfiles(
+    patchdir: &Path,
+    distinfo: &Distinfo,
+) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    if !patchdir.is_dir() {
+        return Ok(files);
+    }
+    for file in fs::read_dir(patchdir)? {
+        let file = file?;
+        if !file.file_type()?.is_file() {
+            continue;
+        }
+        let filename = PathBuf::from(file.file_name());
+        if Entry::is_patch_filename(&filename)
+            && distinfo.patchfile(&filename).is_none()
+        {
+            files.push(filename);
+        }
     }
+    Ok(files)
 }
@@ -15,19 +15,43 @@
  */
 
 use crate::build_thread_pool;
+use crate::hasher::{Algorithm, MultiHasher, hash_path};
 use clap::Args;
 use pkgsrc::digest::Digest;
+use pkgsrc::distinfo::EntryType;
 use rayon::prelude::*;
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use walkdir::WalkDir;
 
 #[derive(Args, Debug)]
 pub struct DigestCmd {
     #[arg(value_name = "algorithm")]
-    #[arg(help = "Algorithm to use")]
-    algorithm: String,
+    #[arg(required_unless_present_any = ["check", "algorithms"])]
+    #[arg(help = "Algorithm to use (comma-separated for multiple)")]
+    algorithm: Option<String>,
+
+    #[arg(short = 'a', value_name = "algorithm", value_delimiter = ',')]
+    #[arg(
+        help = "Algorithm to use, all arguments are files (may be repeated)"
+    )]
+    algorithms: Vec<String>,
+
+    #[arg(short = 'c', value_name = "file", conflicts_with = "files")]
+    #[arg(help = "Read checksums from file (\"-\" for stdin) and verify \
+                  (without an algorithm, untagged lines must be MD5, SHA1, \
+                  SHA256 or SHA512)")]
+    check: Option<PathBuf>,
+
+    #[arg(short = 'r', value_name = "dir", conflicts_with_all = ["files", "check"])]
+    #[arg(help = "Recursively hash all files under dir and print a manifest")]
+    recursive: Option<PathBuf>,
+
+    #[arg(short = 't', requires = "recursive")]
+    #[arg(help = "With -r, print a single digest of the entire tree")]
+    tree: bool,
 
     #[arg(short = 'j', value_name = "jobs")]
     #[arg(help = "Maximum number of threads (or \"MKTOOL_JOBS\" env var)")]
@@ -40,22 +64,150 @@ pub struct DigestCmd {
 
 struct DigestResult {
     path: PathBuf,
-    hash: Option<String>,
+    hashes: Option<Vec<String>>,
     error: String,
 }
 
+/*
+ * A single entry found when walking a directory in -r mode.  Regular files
+ * are hashed, symlinks record their target.
+ */
+enum TreeEntry {
+    File(DigestResult),
+    Symlink(PathBuf, PathBuf),
+}
+
+/*
+ * A single line parsed from a checksum file in -c mode.
+ */
+struct CheckLine {
+    algorithm: Algorithm,
+    path: PathBuf,
+    expected: String,
+    result: Option<Result<String, String>>,
+}
+
+/*
+ * Algorithm for an untagged line based on the length of its hash.  Only the
+ * common coreutils algorithms are considered, as other lengths are shared
+ * by too many algorithms to guess.
+ */
+fn infer_algorithm(hash: &str) -> Option<Algorithm> {
+    let digest = match hash.len() {
+        32 => Digest::MD5,
+        40 => Digest::SHA1,
+        64 => Digest::SHA256,
+        128 => Digest::SHA512,
+        _ => return None,
+    };
+    Some(digest.into())
+}
+
+/*
+ * Parse a line of checksum output.  Two formats are supported: our own (and
+ * BSD-style) "ALG (path) = hash", and GNU coreutils "hash  path", where the
+ * second separator character is '*' for binary mode.  The latter does not
+ * include an algorithm, so uses the one supplied, or if none was then one
+ * inferred from the hash length.  Returns an error if there is no way to
+ * know the algorithm, and None if the line is malformed.
+ */
+fn parse_check_line(
+    line: &str,
+    default: Option<Algorithm>,
+) -> Option<Result<CheckLine, &'static str>> {
+    if let Some((alg, rest)) = line.split_once(" (") {
+        if let Some((path, hash)) = rest.rsplit_once(") = ") {
+            if let Ok(algorithm) = Algorithm::from_str(alg) {
+                if !hash.is_empty()
+                    && hash.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return Some(Ok(CheckLine {
+                        algorithm,
+                        path: PathBuf::from(path),
+                        expected: hash.to_lowercase(),
+                        result: None,
+                    }));
+                }
+            }
+        }
+    }
+    let (hash, rest) = line.split_once(' ')?;
+    let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    if path.is_empty() {
+        return None;
+    }
+    let Some(algorithm) = default.or_else(|| infer_algorithm(hash)) else {
+        return Some(Err("algorithm required for untagged lines"));
+    };
+    Some(Ok(CheckLine {
+        algorithm,
+        path: PathBuf::from(path),
+        expected: hash.to_lowercase(),
+        result: None,
+    }))
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    if n == 1 { format!("{n} {one}") } else { format!("{n} {many}") }
+}
+
 impl DigestCmd {
     pub fn run(&self) -> Result<i32, Box<dyn std::error::Error>> {
-        let algorithm = Digest::from_str(&self.algorithm)?;
+        /*
+         * Algorithms may be given either as the first argument or via -a,
+         * and either may be a comma-separated list.  If -a is used then
+         * every argument is a file, but clap will have parsed the first of
+         * them as the algorithm, so put it back.
+         */
+        let mut files: Option<Vec<PathBuf>> = self.files.clone();
+        let mut algorithms: Vec<Algorithm> = vec![];
+        if let Some(a) = &self.algorithm {
+            if self.algorithms.is_empty() {
+                for alg in a.split(',') {
+                    algorithms.push(Algorithm::from_str(alg)?);
+                }
+            } else if self.check.is_some() || self.recursive.is_some() {
+                eprintln!(
+                    "digest: file arguments cannot be used with -c or -r"
+                );
+                return Ok(1);
+            } else {
+                files.get_or_insert_with(Vec::new).insert(0, a.into());
+            }
+        }
+        for a in &self.algorithms {
+            algorithms.push(Algorithm::from_str(a)?);
+        }
+
+        if let Some(check) = &self.check {
+            if algorithms.len() > 1 {
+                eprintln!(
+                    "digest: only a single algorithm is supported with -c"
+                );
+                return Ok(1);
+            }
+            return self.run_check(check, algorithms.first().copied());
+        }
+
+        if let Some(dir) = &self.recursive {
+            return self.run_recursive(dir, &algorithms);
+        }
 
         /*
          * If we're passed any input files then set them up for parallel
          * processing, otherwise we operate in stdin mode: just perform the
-         * calculation immediately and return.
+         * calculation immediately and return.  Each file is only read once
+         * regardless of the number of algorithms.
          */
-        let Some(files) = &self.files else {
-            let mut stdin = io::stdin().lock();
-            println!("{}", algorithm.hash_file(&mut stdin)?);
+        let Some(files) = files else {
+            let mut hasher = MultiHasher::new(&algorithms);
+            hasher.update_reader(&mut io::stdin().lock())?;
+            for hash in hasher.finalize().hashes {
+                println!("{hash}");
+            }
             return Ok(0);
         };
 
@@ -69,18 +221,15 @@ impl DigestCmd {
             .iter()
             .map(|f| DigestResult {
                 path: f.to_path_buf(),
-                hash: None,
+                hashes: None,
                 error: String::new(),
             })
             .collect();
 
         pool.install(|| {
             hashfiles.par_iter_mut().for_each(|file| {
-                match fs::File::open(&file.path) {
-                    Ok(mut f) => match algorithm.hash_file(&mut f) {
-                        Ok(h) => file.hash = Some(h),
-                        Err(e) => file.error = e.to_string(),
-                    },
+                match hash_path(&file.path, &algorithms, &EntryType::Distfile) {
+                    Ok(res) => file.hashes = Some(res.hashes),
                     Err(e) => file.error = e.to_string(),
                 }
             });
@@ -93,8 +242,15 @@ impl DigestCmd {
          */
         let mut rv = 0;
         for file in hashfiles {
-            if let Some(hash) = file.hash {
-                println!("{} ({}) = {}", algorithm, file.path.display(), hash);
+            if let Some(hashes) = file.hashes {
+                for (algorithm, hash) in algorithms.iter().zip(hashes) {
+                    println!(
+                        "{} ({}) = {}",
+                        algorithm,
+                        file.path.display(),
+                        hash
+                    );
+                }
             } else {
                 eprintln!("{}: {}", file.path.display(), file.error);
                 rv = 1;
@@ -103,4 +259,246 @@ impl DigestCmd {
 
         Ok(rv)
     }
+
+    /*
+     * Verify files listed in a checksum file, producing output and an exit
+     * status compatible with "sha256sum -c" and friends.
+     */
+    fn run_check(
+        &self,
+        check: &Path,
+        algorithm: Option<Algorithm>,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let reader: Box<dyn io::BufRead> = match check.to_str() {
+            Some("-") => Box::new(io::stdin().lock()),
+            _ => match fs::File::open(check) {
+                Ok(f) => Box::new(BufReader::new(f)),
+                Err(e) => {
+                    eprintln!("digest: {}: {e}", check.display());
+                    return Ok(1);
+                }
+            },
+        };
+
+        let mut lines: Vec<CheckLine> = vec![];
+        let mut badlines = 0;
+        for line in reader.lines() {
+            let line = line?;
+            match parse_check_line(&line, algorithm) {
+                Some(Ok(l)) => lines.push(l),
+                Some(Err(e)) => {
+                    eprintln!("digest: {}: {e}", check.display());
+                    return Ok(1);
+                }
+                None => badlines += 1,
+            }
+        }
+        if lines.is_empty() {
+            eprintln!(
+                "digest: {}: no properly formatted checksum lines found",
+                check.display()
+            );
+            return Ok(1);
+        }
+
+        let pool = build_thread_pool(self.jobs)?;
+        pool.install(|| {
+            lines.par_iter_mut().for_each(|line| {
+                line.result = Some(
+                    hash_path(
+                        &line.path,
+                        &[line.algorithm],
+                        &EntryType::Distfile,
+                    )
+                    .map(|mut res| res.hashes.remove(0))
+                    .map_err(|e| e.to_string()),
+                );
+            });
+        });
+
+        let mut failed = 0;
+        let mut unreadable = 0;
+        for line in &lines {
+            let path = line.path.display();
+            match &line.result {
+                Some(Ok(hash)) if *hash == line.expected => {
+                    println!("{path}: OK");
+                }
+                Some(Ok(_)) => {
+                    println!("{path}: FAILED");
+                    failed += 1;
+                }
+                Some(Err(e)) => {
+                    eprintln!("digest: {path}: {e}");
+                    println!("{path}: FAILED open or read");
+                    unreadable += 1;
+                }
+                None => {}
+            }
+        }
+
+        if badlines > 0 {
+            eprintln!(
+                "digest: WARNING: {} improperly formatted",
+                plural(badlines, "line is", "lines are")
+            );
+        }
+        if unreadable > 0 {
+            eprintln!(
+                "digest: WARNING: {} could not be read",
+                plural(unreadable, "listed file", "listed files")
+            );
+        }
+        if failed > 0 {
+            eprintln!(
+                "digest: WARNING: {} did NOT match",
+                plural(failed, "computed checksum", "computed checksums")
+            );
+        }
+
+        Ok(i32::from(failed > 0 || unreadable > 0))
+    }
+
+    /*
+     * Hash every regular file under a directory and print a manifest sorted
+     * by path, relative to the top directory.  Symlinks are not followed,
+     * and are recorded as their target instead.  Other file types and
+     * empty directories are ignored.
+     *
+     * With -t the manifest itself is hashed with each algorithm instead,
+     * giving a single deterministic digest for the entire tree.
+     */
+    fn run_recursive(
+        &self,
+        dir: &Path,
+        algorithms: &[Algorithm],
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let mut rv = 0;
+        let mut entries: Vec<TreeEntry> = vec![];
+        /*
+         * Paths are relative to dir, unless it is not a directory, in which
+         * case the manifest contains just that file relative to its parent.
+         */
+        let base = if dir.is_dir() { dir } else { dir.parent().unwrap_or(dir) };
+        for entry in WalkDir::new(dir).follow_links(false) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    eprintln!("{e}");
+                    rv = 1;
+                    continue;
+                }
+            };
+            let path = entry.path().strip_prefix(base).unwrap_or(entry.path());
+            if entry.file_type().is_file() {
+                entries.push(TreeEntry::File(DigestResult {
+                    path: path.to_path_buf(),
+                    hashes: None,
+                    error: String::new(),
+                }));
+            } else if entry.file_type().is_symlink() {
+                match fs::read_link(entry.path()) {
+                    Ok(target) => entries
+                        .push(TreeEntry::Symlink(path.to_path_buf(), target)),
+                    Err(e) => {
+                        eprintln!("{}: {e}", entry.path().display());
+                        rv = 1;
+                    }
+                }
+            }
+        }
+
+        let pool = build_thread_pool(self.jobs)?;
+        pool.install(|| {
+            entries.par_iter_mut().for_each(|entry| {
+                if let TreeEntry::File(file) = entry {
+                    match hash_path(
+                        base.join(&file.path),
+                        algorithms,
+                        &EntryType::Distfile,
+                    ) {
+                        Ok(res) => file.hashes = Some(res.hashes),
+                        Err(e) => file.error = e.to_string(),
+                    }
+                }
+            });
+        });
+
+        /*
+         * Sort by raw path bytes so that the output does not depend on the
+         * order that the filesystem returns entries, or the locale.
+         */
+        entries.sort_by(|a, b| {
+            let path = |e: &TreeEntry| match e {
+                TreeEntry::File(f) => f.path.clone(),
+                TreeEntry::Symlink(p, _) => p.clone(),
+            };
+            path(a).as_os_str().cmp(path(b).as_os_str())
+        });
+
+        /*
+         * Generate the manifest for each algorithm.  Without -t these are
+         * printed interleaved by file, to match the regular output format.
+         */
+        let mut manifests: Vec<String> = vec![String::new(); algorithms.len()];
+        let mut out = String::new();
+        for entry in &entries {
+            match entry {
+                TreeEntry::File(file) => {
+                    let Some(hashes) = &file.hashes else {
+                        eprintln!(
+                            "{}: {}",
+                            base.join(&file.path).display(),
+                            file.error
+                        );
+                        rv = 1;
+                        continue;
+                    };
+                    for (i, (algorithm, hash)) in
+                        algorithms.iter().zip(hashes).enumerate()
+                    {
+                        let line = format!(
+                            "{} ({}) = {}\n",
+                            algorithm,
+                            file.path.display(),
+                            hash
+                        );
+                        manifests[i].push_str(&line);
+                        out.push_str(&line);
+                    }
+                }
+                TreeEntry::Symlink(path, target) => {
+                    let line = format!(
+                        "Symlink ({}) -> {}\n",
+                        path.display(),
+                        target.display()
+                    );
+                    for m in &mut manifests {
+                        m.push_str(&line);
+                    }
+                    out.push_str(&line);
+                }
+            }
+        }
+
+        /*
+         * A tree digest that is missing any files would look valid but be
+         * wrong, so only print it if everything was read successfully.
+         */
+        if self.tree {
+            if rv != 0 {
+                return Ok(rv);
+            }
+            for (algorithm, manifest) in algorithms.iter().zip(manifests) {
+                let mut hasher = MultiHasher::new(&[*algorithm]);
+                hasher.update(manifest.as_bytes());
+                let hash = hasher.finalize().hashes.remove(0);
+                println!("{} ({}) = {}", algorithm, dir.display(), hash);
+            }
+        } else {
+            print!("{out}");
+        }
+
+        Ok(rv)
+    }
 }
@@ -15,9 +15,10 @@
  */
 
 use crate::build_thread_pool;
+use crate::distinfo_file::{Checksum, Distinfo, Entry};
+use crate::hasher::{Algorithm, hash_path};
 use clap::Args;
-use pkgsrc::digest::Digest;
-use pkgsrc::distinfo::{Checksum, Distinfo, Entry, EntryType};
+use pkgsrc::distinfo::EntryType;
 use rayon::prelude::*;
 use std::collections::HashSet;
 use std::fs;
@@ -153,13 +154,13 @@ impl DistInfo {
          */
         let mut distsums: Vec<Checksum> = vec![];
         for algorithm in &self.dalgorithms {
-            let digest = Digest::from_str(algorithm)?;
+            let digest = Algorithm::from_str(algorithm)?;
             distsums.push(Checksum::new(digest, String::new()));
         }
         for distfile in distfiles {
             let mut fullpath = PathBuf::from(&self.distdir);
             fullpath.push(&distfile);
-            let entry = Entry::new(distfile, fullpath, distsums.clone(), None);
+            let entry = Entry::new(distfile, fullpath, distsums.clone());
             entries.push(entry);
         }
 
@@ -169,7 +170,7 @@ impl DistInfo {
          */
         let mut patchsums: Vec<Checksum> = vec![];
         for algorithm in &self.palgorithms {
-            let digest = Digest::from_str(algorithm)?;
+            let digest = Algorithm::from_str(algorithm)?;
             patchsums.push(Checksum::new(digest, String::new()));
         }
         entries.extend(
@@ -178,12 +179,7 @@ impl DistInfo {
                 .filter(|p| p.exists() && Entry::is_patch_filename(p))
                 .filter_map(|path| {
                     path.file_name().map(|f| {
-                        Entry::new(
-                            PathBuf::from(f),
-                            path,
-                            patchsums.clone(),
-                            None,
-                        )
+                        Entry::new(PathBuf::from(f), path, patchsums.clone())
                     })
                 }),
         );
@@ -213,37 +209,31 @@ impl DistInfo {
 
         /*
          * Calculate checksums for each Entry, and size for Distfile entries,
-         * storing results back into the Entry.
+         * storing results back into the Entry.  Each file is only read once
+         * regardless of the number of digests requested.
          */
         pool.install(|| {
             entries.par_iter_mut().for_each(|entry| {
-                for c in entry.checksums.iter_mut() {
-                    match Distinfo::calculate_checksum(
-                        &entry.filepath,
-                        c.digest,
-                    ) {
-                        Ok(h) => c.hash = h,
-                        Err(e) => {
-                            eprintln!(
-                                "Unable to calculate checksum for {}: {}",
-                                entry.filepath.display(),
-                                e
-                            );
+                let digests: Vec<Algorithm> =
+                    entry.checksums.iter().map(|c| c.digest).collect();
+                match hash_path(&entry.filepath, &digests, &entry.filetype) {
+                    Ok(res) => {
+                        for (c, h) in entry.checksums.iter_mut().zip(res.hashes)
+                        {
+                            c.hash = h;
                         }
-                    };
-                }
-                if entry.filetype == EntryType::Distfile {
-                    match Distinfo::calculate_size(&entry.filepath) {
-                        Ok(s) => entry.size = Some(s),
-                        Err(e) => {
-                            eprintln!(
-                                "Unable to calculate size for {}: {}",
-                                entry.filepath.display(),
-                                e
-                            );
+                        if entry.filetype == EntryType::Distfile {
+                            entry.size = Some(res.size);
                         }
-                    };
-                }
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "Unable to calculate checksum for {}: {}",
+                            entry.filepath.display(),
+                            e
+                        );
+                    }
+                };
             });
         });
 
@@ -0,0 +1,375 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Read and write distinfo files.
+ *
+ * This follows pkgsrc::distinfo, except that each checksum records an
+ * Algorithm rather than a pkgsrc::digest::Digest.  pkgsrc::distinfo ignores
+ * any line for an algorithm that Digest does not implement, so a distinfo
+ * using e.g. BLAKE3 would appear to have no checksums at all.  Here those
+ * lines are parsed, verified and written back out like any other.
+ */
+
+use crate::hasher::Algorithm;
+use pkgsrc::distinfo::EntryType;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Checksum {
+    pub digest: Algorithm,
+    pub hash: String,
+}
+
+impl Checksum {
+    pub fn new(digest: Algorithm, hash: String) -> Checksum {
+        Checksum { digest, hash }
+    }
+}
+
+/*
+ * A file listed in distinfo.  filename is as recorded in distinfo, and may
+ * include a DIST_SUBDIR, while filepath is where it is found on disk.
+ */
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Entry {
+    pub filename: PathBuf,
+    pub filepath: PathBuf,
+    pub size: Option<u64>,
+    pub checksums: Vec<Checksum>,
+    pub filetype: EntryType,
+}
+
+#[derive(Debug, Error)]
+pub enum DistinfoError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("File not found")]
+    NotFound,
+    #[error("Checksum {1} mismatch for {0}: expected {2}, actual {3}")]
+    Checksum(PathBuf, Algorithm, String, String),
+    #[error("Missing {1} checksum entry for {0}")]
+    MissingChecksum(PathBuf, Algorithm),
+    #[error("Size mismatch for {0}: expected {1}, actual {2}")]
+    Size(PathBuf, u64, u64),
+    #[error("Missing size entry for {0}")]
+    MissingSize(PathBuf),
+}
+
+impl Entry {
+    pub fn new<P1: AsRef<Path>, P2: AsRef<Path>>(
+        filename: P1,
+        filepath: P2,
+        checksums: Vec<Checksum>,
+    ) -> Entry {
+        Entry {
+            filename: filename.as_ref().to_path_buf(),
+            filepath: filepath.as_ref().to_path_buf(),
+            size: None,
+            checksums,
+            filetype: EntryType::from(filename.as_ref()),
+        }
+    }
+
+    /*
+     * Patches are named the same however their checksums are recorded.
+     */
+    pub fn is_patch_filename<P: AsRef<Path>>(path: P) -> bool {
+        pkgsrc::distinfo::Entry::is_patch_filename(path)
+    }
+
+    /*
+     * Verify that a file matches the recorded size, returning it if so.
+     */
+    pub fn verify_size<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<u64, DistinfoError> {
+        let Some(size) = self.size else {
+            return Err(DistinfoError::MissingSize(path.as_ref().into()));
+        };
+        let fsize = fs::metadata(path)?.len();
+        if fsize != size {
+            return Err(DistinfoError::Size(
+                self.filename.clone(),
+                size,
+                fsize,
+            ));
+        }
+        Ok(size)
+    }
+
+    /*
+     * Lines for this entry in distinfo order: checksums followed by size.
+     */
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let name = self.filename.display();
+        for c in &self.checksums {
+            bytes.extend_from_slice(
+                format!("{} ({name}) = {}\n", c.digest, c.hash).as_bytes(),
+            );
+        }
+        if let Some(size) = self.size {
+            bytes.extend_from_slice(
+                format!("Size ({name}) = {size} bytes\n").as_bytes(),
+            );
+        }
+    }
+}
+
+/*
+ * A parsed distinfo line.
+ */
+enum Line {
+    RcsId(OsString),
+    Size(PathBuf, u64),
+    Checksum(Algorithm, PathBuf, String),
+}
+
+impl Line {
+    /*
+     * As with pkgsrc::distinfo, anything that is not recognised is ignored
+     * rather than treated as an error.
+     */
+    fn from_bytes(line: &[u8]) -> Option<Line> {
+        let line = line.trim_ascii_start();
+        if line.starts_with(b"$NetBSD: ") {
+            return Some(Line::RcsId(OsString::from_vec(line.to_vec())));
+        }
+
+        /*
+         * Both types of line have the important parts in the same place:
+         *
+         *   ALGORITHM (FILENAME) = HASH
+         *   Size (FILENAME) = BYTES bytes
+         */
+        let fields: Vec<&[u8]> = line
+            .split(|c| c.is_ascii_whitespace())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let [action, path, _, value, ..] = fields[..] else {
+            return None;
+        };
+        let action = std::str::from_utf8(action).ok()?;
+        let value = std::str::from_utf8(value).ok()?;
+        let path = path.strip_prefix(b"(")?.strip_suffix(b")")?;
+        let path = PathBuf::from(OsStr::from_bytes(path));
+        if action == "Size" {
+            return Some(Line::Size(path, u64::from_str(value).ok()?));
+        }
+        let digest = Algorithm::from_str(action).ok()?;
+        Some(Line::Checksum(digest, path, value.to_string()))
+    }
+}
+
+/*
+ * Distfiles and patchfiles are each kept in the order they were added.
+ */
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Distinfo {
+    rcsid: Option<OsString>,
+    distfiles: Vec<Entry>,
+    patchfiles: Vec<Entry>,
+}
+
+impl Distinfo {
+    pub fn new() -> Distinfo {
+        Distinfo::default()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Distinfo {
+        let mut distinfo = Distinfo::new();
+        for line in bytes.split(|c| *c == b'\n') {
+            match Line::from_bytes(line) {
+                Some(Line::RcsId(s)) => distinfo.rcsid = Some(s),
+                Some(Line::Size(path, size)) => {
+                    distinfo.entry_mut(&path).size = Some(size);
+                }
+                Some(Line::Checksum(digest, path, hash)) => {
+                    let entry = distinfo.entry_mut(&path);
+                    entry.checksums.push(Checksum::new(digest, hash));
+                }
+                None => {}
+            }
+        }
+        distinfo
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match &self.rcsid {
+            Some(s) => bytes.extend_from_slice(s.as_bytes()),
+            None => bytes.extend_from_slice(b"$NetBSD$"),
+        }
+        bytes.extend_from_slice(b"\n\n");
+        for entry in self.distfiles.iter().chain(&self.patchfiles) {
+            entry.write(&mut bytes);
+        }
+        bytes
+    }
+
+    pub fn rcsid(&self) -> Option<&OsString> {
+        self.rcsid.as_ref()
+    }
+
+    pub fn set_rcsid(&mut self, rcsid: impl Into<OsString>) {
+        self.rcsid = Some(rcsid.into());
+    }
+
+    pub fn distfile<P: AsRef<Path>>(&self, path: P) -> Option<&Entry> {
+        self.distfiles.iter().find(|e| e.filename == path.as_ref())
+    }
+
+    pub fn patchfile<P: AsRef<Path>>(&self, path: P) -> Option<&Entry> {
+        self.patchfiles.iter().find(|e| e.filename == path.as_ref())
+    }
+
+    pub fn distfiles(&self) -> Vec<&Entry> {
+        self.distfiles.iter().collect()
+    }
+
+    pub fn patchfiles(&self) -> Vec<&Entry> {
+        self.patchfiles.iter().collect()
+    }
+
+    /*
+     * Add an entry, replacing any existing entry with the same filename in
+     * its current position.  Returns true if the entry is new.
+     */
+    pub fn insert(&mut self, entry: Entry) -> bool {
+        let entries = match entry.filetype {
+            EntryType::Distfile => &mut self.distfiles,
+            EntryType::Patchfile => &mut self.patchfiles,
+        };
+        match entries.iter_mut().find(|e| e.filename == entry.filename) {
+            Some(e) => {
+                *e = entry;
+                false
+            }
+            None => {
+                entries.push(entry);
+                true
+            }
+        }
+    }
+
+    /*
+     * Distfile entries may include a DIST_SUBDIR, so a path cannot simply
+     * be looked up by its filename.  Instead try each trailing portion of
+     * the path, shortest first, until an entry is found.
+     */
+    pub fn find_entry<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<&Entry, DistinfoError> {
+        let filetype = EntryType::from(path.as_ref());
+        let components: Vec<&OsStr> = path.as_ref().iter().collect();
+        for i in (0..components.len()).rev() {
+            let file: PathBuf = components[i..].iter().collect();
+            let entry = match filetype {
+                EntryType::Distfile => self.distfile(&file),
+                EntryType::Patchfile => self.patchfile(&file),
+            };
+            if let Some(entry) = entry {
+                return Ok(entry);
+            }
+        }
+        Err(DistinfoError::NotFound)
+    }
+
+    pub fn verify_size<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<u64, DistinfoError> {
+        self.find_entry(path.as_ref())?.verify_size(path)
+    }
+
+    /*
+     * Return the entry for a path, creating it if necessary.
+     */
+    fn entry_mut(&mut self, path: &Path) -> &mut Entry {
+        let filetype = EntryType::from(path);
+        let entries = match filetype {
+            EntryType::Distfile => &mut self.distfiles,
+            EntryType::Patchfile => &mut self.patchfiles,
+        };
+        match entries.iter().position(|e| e.filename == path) {
+            Some(i) => &mut entries[i],
+            None => {
+                entries.push(Entry {
+                    filename: path.to_path_buf(),
+                    filetype,
+                    ..Default::default()
+                });
+                let n = entries.len();
+                &mut entries[n - 1]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkgsrc::digest::Digest;
+
+    /*
+     * Lines for algorithms that pkgsrc::distinfo does not support must be
+     * retained, in their original order, and written back out unchanged.
+     */
+    #[test]
+    fn test_roundtrip() {
+        let input = "$NetBSD: distinfo,v 1.1 1970/01/01 00:00:00 ken Exp $\n\
+                     \n\
+                     BLAKE3 (sub/a-1.0.tar.gz) = af13\n\
+                     SHA512 (sub/a-1.0.tar.gz) = cf83\n\
+                     Size (sub/a-1.0.tar.gz) = 123 bytes\n\
+                     SHA3-256 (patch-aa) = a7ff\n";
+        let distinfo = Distinfo::from_bytes(input.as_bytes());
+        assert_eq!(distinfo.as_bytes(), input.as_bytes());
+
+        let entry = distinfo.find_entry("/distdir/sub/a-1.0.tar.gz").unwrap();
+        assert_eq!(entry.filename, PathBuf::from("sub/a-1.0.tar.gz"));
+        assert_eq!(entry.size, Some(123));
+        assert_eq!(
+            entry.checksums.iter().map(|c| c.digest).collect::<Vec<_>>(),
+            [Algorithm::BLAKE3, Algorithm::Pkgsrc(Digest::SHA512)]
+        );
+        let entry = distinfo.find_entry("patches/patch-aa").unwrap();
+        assert_eq!(entry.filetype, EntryType::Patchfile);
+        assert!(distinfo.find_entry("a-1.0.tar.gz").is_err());
+    }
+
+    /*
+     * Anything unrecognised is ignored.
+     */
+    #[test]
+    fn test_invalid() {
+        let input = "# comment\n\
+                     \n\
+                     SHA3-512 (a) = 1234\n\
+                     SHA1 a = 1234\n\
+                     SHA1 (a)\n\
+                     Size (a) = many bytes\n";
+        assert_eq!(Distinfo::from_bytes(input.as_bytes()), Distinfo::new());
+    }
+}
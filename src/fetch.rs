@@ -15,9 +15,11 @@
  */
 
 use crate::build_thread_pool;
+use crate::cache::HashCache;
+use crate::distinfo_file::Distinfo;
+use crate::hasher::{entry_digests, verify_checksums};
 use clap::Args;
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
-use pkgsrc::distinfo::Distinfo;
 use rayon::prelude::*;
 use reqwest::blocking::Client;
 use std::env;
@@ -208,12 +210,19 @@ impl Fetch {
          */
         let client = build_client()?;
 
+        /*
+         * Record the hashes of verified downloads in the checksum cache if
+         * enabled, so that the checksum phase need not calculate them again.
+         */
+        let cache = HashCache::from_env();
+
         pool.install(|| {
             files.par_iter_mut().for_each(|file| {
                 if let Err(e) = fetch_and_verify(
                     &client,
                     file,
                     distinfo.as_ref(),
+                    cache.as_ref(),
                     &progress,
                 ) {
                     progress.suspend(|| {
@@ -228,6 +237,9 @@ impl Fetch {
         });
 
         progress.finish_and_clear();
+        if let Some(cache) = &cache {
+            cache.save_or_warn();
+        }
 
         let rv = i32::from(files.iter().any(|f| !f.status));
 
@@ -335,6 +347,7 @@ fn fetch_and_verify(
     client: &Client,
     file: &FetchFile,
     distinfo: Option<&Distinfo>,
+    cache: Option<&HashCache>,
     progress: &ProgressBar,
 ) -> Result<u64, FetchError> {
     // Set the target filename
@@ -422,7 +435,12 @@ fn fetch_and_verify(
                 Ok(_) => {
                     if let Some(di) = distinfo {
                         if let Some(entry) = di.distfile(&file.filepath) {
-                            for result in entry.verify_checksums(&temp_name) {
+                            for result in verify_checksums(
+                                entry,
+                                &temp_name,
+                                &entry_digests(entry),
+                                cache,
+                            ) {
                                 if let Err(e) = result {
                                     progress.suspend(|| {
                                         eprintln!(
@@ -483,7 +501,12 @@ fn fetch_and_verify(
                 drop(tempfile);
                 if let Some(di) = distinfo {
                     if let Some(entry) = di.distfile(&file.filepath) {
-                        for result in entry.verify_checksums(&temp_name) {
+                        for result in verify_checksums(
+                            entry,
+                            &temp_name,
+                            &entry_digests(entry),
+                            cache,
+                        ) {
                             if let Err(e) = result {
                                 progress.suspend(|| {
                                     eprintln!(
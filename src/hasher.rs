@@ -0,0 +1,452 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/*
+ * Calculate any number of digests over a file in a single pass.
+ *
+ * pkgsrc::digest::Digest only supports hashing a reader with one algorithm
+ * at a time, so a distfile with BLAKE2s, SHA512 and a Size entry would be
+ * read three times.  Here we feed each block read to every hasher at once,
+ * counting the size as we go.  Large files are mapped into memory rather
+ * than copied through a buffer.
+ *
+ * Algorithm extends pkgsrc::digest::Digest with newer algorithms.  As
+ * pkgsrc::distinfo can only record a Digest, distinfo files are handled by
+ * distinfo_file instead, so that these are available to every command.
+ */
+
+use crate::cache::HashCache;
+use crate::distinfo_file::{DistinfoError, Entry};
+use digest::DynDigest;
+use digest::InvalidBufferSize;
+use memmap2::Mmap;
+use pkgsrc::digest::{Digest, DigestError};
+use pkgsrc::distinfo::EntryType;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+/*
+ * Files at least this size are hashed via mmap(2).  Below this the setup
+ * cost outweighs any benefit over plain reads.
+ */
+const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+/*
+ * Read buffer size for files that are not mapped, and for stdin.
+ */
+const READ_BUFSIZE: usize = 64 * 1024;
+
+/*
+ * Results of hashing a single file.  Hashes are returned in the same order
+ * as the digests that were requested.
+ */
+#[derive(Debug)]
+pub struct FileHashes {
+    pub hashes: Vec<String>,
+    pub size: u64,
+}
+
+/*
+ * Every algorithm that can be calculated.  Names are case-insensitive when
+ * parsed, as with Digest.
+ */
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Algorithm {
+    Pkgsrc(Digest),
+    BLAKE3,
+    SHA3_256,
+}
+
+impl From<Digest> for Algorithm {
+    fn from(digest: Digest) -> Algorithm {
+        Algorithm::Pkgsrc(digest)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = DigestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blake3" => Ok(Algorithm::BLAKE3),
+            "sha3-256" => Ok(Algorithm::SHA3_256),
+            _ => Digest::from_str(s).map(Algorithm::Pkgsrc),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algorithm::Pkgsrc(d) => write!(f, "{d}"),
+            Algorithm::BLAKE3 => write!(f, "BLAKE3"),
+            Algorithm::SHA3_256 => write!(f, "SHA3-256"),
+        }
+    }
+}
+
+/*
+ * The blake3 crate implements a different version of the digest traits to
+ * the RustCrypto hashers we use, so wrap it in our own DynDigest.
+ */
+#[derive(Clone, Default)]
+struct Blake3(blake3::Hasher);
+
+impl DynDigest for Blake3 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize_into(self, buf: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        let mut this = self;
+        this.finalize_into_reset(buf)
+    }
+
+    fn finalize_into_reset(
+        &mut self,
+        out: &mut [u8],
+    ) -> Result<(), InvalidBufferSize> {
+        if out.len() != blake3::OUT_LEN {
+            return Err(InvalidBufferSize);
+        }
+        out.copy_from_slice(self.0.finalize().as_bytes());
+        self.0.reset();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn output_size(&self) -> usize {
+        blake3::OUT_LEN
+    }
+
+    fn box_clone(&self) -> Box<dyn DynDigest> {
+        Box::new(self.clone())
+    }
+}
+
+fn new_hasher(algorithm: Algorithm) -> Box<dyn DynDigest + Send> {
+    match algorithm {
+        Algorithm::Pkgsrc(Digest::BLAKE2s) => {
+            Box::new(blake2::Blake2s256::default())
+        }
+        Algorithm::Pkgsrc(Digest::MD5) => Box::new(md5::Md5::default()),
+        Algorithm::Pkgsrc(Digest::RMD160) => {
+            Box::new(ripemd::Ripemd160::default())
+        }
+        Algorithm::Pkgsrc(Digest::SHA1) => Box::new(sha1::Sha1::default()),
+        Algorithm::Pkgsrc(Digest::SHA256) => Box::new(sha2::Sha256::default()),
+        Algorithm::Pkgsrc(Digest::SHA512) => Box::new(sha2::Sha512::default()),
+        Algorithm::BLAKE3 => Box::new(Blake3::default()),
+        Algorithm::SHA3_256 => Box::new(sha3::Sha3_256::default()),
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(s, "{b:02x}");
+    }
+    s
+}
+
+/*
+ * A set of hashers that are all updated together.
+ */
+pub struct MultiHasher {
+    hashers: Vec<Box<dyn DynDigest + Send>>,
+    size: u64,
+}
+
+impl MultiHasher {
+    pub fn new<A: Copy + Into<Algorithm>>(digests: &[A]) -> MultiHasher {
+        MultiHasher {
+            hashers: digests.iter().map(|d| new_hasher((*d).into())).collect(),
+            size: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for h in &mut self.hashers {
+            h.update(data);
+        }
+        self.size += data.len() as u64;
+    }
+
+    pub fn finalize(self) -> FileHashes {
+        FileHashes {
+            hashes: self
+                .hashers
+                .into_iter()
+                .map(|h| hex_encode(&h.finalize()))
+                .collect(),
+            size: self.size,
+        }
+    }
+
+    /*
+     * Hash the full contents of a reader, e.g. stdin.
+     */
+    pub fn update_reader<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut buf = vec![0; READ_BUFSIZE];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => self.update(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /*
+     * Hash a pkgsrc patch file.  This must match Digest::hash_patch(), where
+     * any line containing "$NetBSD" is skipped and every line that remains
+     * is terminated with a newline, even if the original file is not.
+     */
+    fn update_patch<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        for line in BufReader::new(reader).split(b'\n') {
+            let line = line?;
+            if line.windows(7).any(|w| w == b"$NetBSD") {
+                continue;
+            }
+            self.update(&line);
+            self.update(b"\n");
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Calculate each of the requested digests for a file in a single read, along
+ * with its size.  Patch files are hashed using the pkgsrc patch rules, in
+ * which case the size is that of the hashed content rather than the file.
+ */
+pub fn hash_path<P: AsRef<Path>, A: Copy + Into<Algorithm>>(
+    path: P,
+    digests: &[A],
+    filetype: &EntryType,
+) -> io::Result<FileHashes> {
+    let file = File::open(path)?;
+    let mut hasher = MultiHasher::new(digests);
+
+    if *filetype == EntryType::Patchfile {
+        hasher.update_patch(file)?;
+        return Ok(hasher.finalize());
+    }
+
+    let len = file.metadata()?.len();
+    if len >= MMAP_THRESHOLD {
+        /*
+         * SAFETY: the mapping is read-only and only lives for the duration
+         * of this function.  If the file is truncated underneath us by
+         * another process then we may receive SIGBUS, but that is no worse
+         * than the checksum mismatch that would be reported otherwise.
+         *
+         * If the mapping fails for any reason (e.g. a filesystem that does
+         * not support it) fall through to regular reads.
+         */
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            hasher.update(&map);
+            return Ok(hasher.finalize());
+        }
+    }
+
+    let mut file = file;
+    hasher.update_reader(&mut file)?;
+    Ok(hasher.finalize())
+}
+
+/*
+ * All digests recorded for an Entry, in distinfo order.
+ */
+pub fn entry_digests(entry: &Entry) -> Vec<Algorithm> {
+    entry.checksums.iter().map(|c| c.digest).collect()
+}
+
+/*
+ * Verify a file against the checksums recorded for its distinfo Entry,
+ * returning a result for each requested digest in the same order.  The file
+ * is only read once however many digests there are, and an optional cache
+ * is consulted for distfiles.
+ */
+pub fn verify_checksums<P: AsRef<Path>>(
+    entry: &Entry,
+    path: P,
+    digests: &[Algorithm],
+    cache: Option<&HashCache>,
+) -> Vec<Result<Algorithm, DistinfoError>> {
+    let path = path.as_ref();
+
+    /*
+     * Patches live in the pkgsrc tree rather than DISTDIR and are small, so
+     * are never cached.
+     */
+    let cache = cache.filter(|_| entry.filetype == EntryType::Distfile);
+    let meta = match cache {
+        Some(_) => fs::metadata(path).ok(),
+        None => None,
+    };
+
+    /*
+     * Look up each digest, first in the cache, and any remaining are then
+     * calculated all together.
+     */
+    let mut hashes: Vec<Option<String>> = vec![None; digests.len()];
+    if let (Some(cache), Some(meta)) = (cache, &meta) {
+        for (hash, digest) in hashes.iter_mut().zip(digests) {
+            *hash = cache.get(meta, *digest);
+        }
+    }
+    let need: Vec<Algorithm> = digests
+        .iter()
+        .zip(&hashes)
+        .filter(|(d, h)| {
+            h.is_none() && entry.checksums.iter().any(|c| c.digest == **d)
+        })
+        .map(|(d, _)| *d)
+        .collect();
+    let mut ioerr: Option<io::Error> = None;
+    if !need.is_empty() {
+        match hash_path(path, &need, &entry.filetype) {
+            Ok(res) => {
+                for (digest, hash) in need.iter().zip(res.hashes) {
+                    if let (Some(cache), Some(meta)) = (cache, &meta) {
+                        cache.insert(meta, *digest, &hash);
+                    }
+                    if let Some(i) = digests.iter().position(|d| d == digest) {
+                        hashes[i] = Some(hash);
+                    }
+                }
+            }
+            Err(e) => ioerr = Some(e),
+        }
+    }
+
+    digests
+        .iter()
+        .zip(hashes)
+        .map(|(digest, hash)| {
+            let Some(c) = entry.checksums.iter().find(|c| c.digest == *digest)
+            else {
+                return Err(DistinfoError::MissingChecksum(
+                    path.to_path_buf(),
+                    *digest,
+                ));
+            };
+            match hash {
+                Some(h) if h == c.hash => Ok(*digest),
+                Some(h) => Err(DistinfoError::Checksum(
+                    entry.filename.clone(),
+                    *digest,
+                    c.hash.clone(),
+                    h,
+                )),
+                None => Err(DistinfoError::Io(match &ioerr {
+                    Some(e) => io::Error::new(e.kind(), e.to_string()),
+                    None => io::Error::other("no hash calculated"),
+                })),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: &[Digest] = &[
+        Digest::BLAKE2s,
+        Digest::MD5,
+        Digest::RMD160,
+        Digest::SHA1,
+        Digest::SHA256,
+        Digest::SHA512,
+    ];
+
+    /*
+     * Every digest must match what pkgsrc::digest calculates, for both
+     * regular reads and mmap, and for patches.
+     */
+    #[test]
+    fn test_hash_path() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let small = dir.path().join("small");
+        let large = dir.path().join("large");
+        let patch = dir.path().join("patch-aa");
+        fs::write(&small, b"hello\n")?;
+        fs::write(&large, vec![b'x'; MMAP_THRESHOLD as usize + 1])?;
+        fs::write(&patch, b"$NetBSD$\n\n--- a\n+++ a\n@@ -1 +1 @@\n-a\n+b")?;
+
+        for (path, filetype) in [
+            (&small, EntryType::Distfile),
+            (&large, EntryType::Distfile),
+            (&patch, EntryType::Patchfile),
+        ] {
+            let res = hash_path(path, ALL, &filetype)?;
+            assert_eq!(res.hashes.len(), ALL.len());
+            if filetype == EntryType::Distfile {
+                assert_eq!(res.size, fs::metadata(path)?.len());
+            }
+            for (digest, hash) in ALL.iter().zip(res.hashes) {
+                let mut f = File::open(path)?;
+                let expected = match filetype {
+                    EntryType::Distfile => digest.hash_file(&mut f),
+                    EntryType::Patchfile => digest.hash_patch(&mut f),
+                }
+                .map_err(io::Error::other)?;
+                assert_eq!(hash, expected, "{digest} {}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * Algorithms that pkgsrc::digest does not support, checked against the
+     * published test vectors for empty input.
+     */
+    #[test]
+    fn test_algorithm() -> Result<(), DigestError> {
+        for (name, expected) in [
+            (
+                "BLAKE3",
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+            ),
+            (
+                "SHA3-256",
+                "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a",
+            ),
+        ] {
+            let algorithm = Algorithm::from_str(&name.to_lowercase())?;
+            assert_eq!(algorithm.to_string(), name);
+            let mut hasher = MultiHasher::new(&[algorithm]);
+            hasher.update(b"");
+            assert_eq!(hasher.finalize().hashes, vec![expected.to_string()]);
+        }
+        assert_eq!(
+            Algorithm::from_str("sha256")?,
+            Algorithm::Pkgsrc(Digest::SHA256)
+        );
+        assert!(Algorithm::from_str("SHA3-512").is_err());
+        Ok(())
+    }
+}
@@ -14,13 +14,16 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
+mod cache;
 mod check_portability;
 mod check_shlibs;
 mod checksum;
 mod ctfconvert;
 mod digest;
 mod distinfo;
+mod distinfo_file;
 mod fetch;
+mod hasher;
 mod symlinks;
 
 const MKTOOL_DEFAULT_THREADS: usize = 4;
@@ -0,0 +1,663 @@
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const MKTOOL: &str = env!("CARGO_BIN_EXE_mktool");
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/*
+ * Each check is reported in the same format as the pkgsrc version, with its
+ * identifier in the explanation.  Only "test ==" is fatal, and commented
+ * lines or non-shell files are ignored.
+ */
+#[test]
+fn check_portability_bashisms() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(
+        dir.path().join("script"),
+        "#!/bin/sh\n\
+         # [[ $RANDOM ]]\n\
+         source ./foo\n\
+         echo ${foo/a/b}\n",
+    )?;
+    fs::write(dir.path().join("notshell"), "#!/bin/bash\nsource ./foo\n")?;
+
+    let cmd = Command::new(MKTOOL)
+        .arg("check-portability")
+        .current_dir(dir.path())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(
+        stderr,
+        "WARNING: [check-portability] => Found source:\n\
+         WARNING: [check-portability] script:3: source ./foo\n\
+         WARNING: [check-portability] => Found \
+         ${var/pattern/replacement}:\n\
+         WARNING: [check-portability] script:4: echo ${foo/a/b}\n"
+    );
+    let stdout = String::from_utf8_lossy(&cmd.stdout);
+    assert!(stdout.contains("This is check \"source\""), "{stdout}");
+    assert!(stdout.contains("\"# mktool: ignore pattern-substitution\""));
+
+    fs::write(dir.path().join("test"), "#!/bin/sh\n[ a == b ]\n")?;
+    let cmd = Command::new(MKTOOL)
+        .arg("check-portability")
+        .current_dir(dir.path())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    assert_eq!(cmd.status.code(), Some(1));
+    assert!(stderr.contains("ERROR: [check-portability] test:2: [ a == b ]"));
+    Ok(())
+}
+
+/*
+ * Commands inside $(...) and backquote command substitutions are checked,
+ * and fixed, but not $((...)) arithmetic.
+ */
+#[test]
+fn check_portability_substitutions() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let script = dir.path().join("script");
+    fs::write(
+        &script,
+        "#!/bin/sh\n\
+         x=$( [ a == b ] && echo y)\n\
+         y=\"`test c == d`\" z=$(echo $(source ./foo))\n\
+         n=$((1 == 1))\n",
+    )?;
+
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--format", "json"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    let found = String::from_utf8(cmd.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<Vec<serde_json::Value>, _>>()?
+        .iter()
+        .map(|v| format!("{}:{}:{}", v["id"], v["line"], v["column"]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        ["\"test-eq\":2:10", "\"test-eq\":3:12", "\"source\":3:30"]
+    );
+
+    let patchdir = dir.path().join("patches");
+    fs::create_dir(&patchdir)?;
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--fix"])
+        .env("PATCHDIR", &patchdir)
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(
+        fs::read_to_string(&script)?,
+        "#!/bin/sh\n\
+         x=$( [ a = b ] && echo y)\n\
+         y=\"`test c = d`\" z=$(echo $(source ./foo))\n\
+         n=$((1 == 1))\n",
+    );
+    Ok(())
+}
+
+/*
+ * The probe autoconf uses to set ECHO_N and ECHO_C runs "echo -n" inside a
+ * case statement to find out what it does, so should not be reported.
+ */
+#[test]
+fn check_portability_echo_probe() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(
+        dir.path().join("configure"),
+        "#!/bin/sh\n\
+         ECHO_C= ECHO_N= ECHO_T=\n\
+         case `echo -n x` in #(((((\n\
+         -n*)\n  \
+           case `echo 'xy\\c'` in\n  \
+           *c*) ECHO_T='\t';;\n  \
+           xy)  ECHO_C='\\c';;\n  \
+           *)   echo `echo ksh88 bug on AIX 6.1` > /dev/null\n       \
+                ECHO_T='\t';;\n  \
+           esac;;\n\
+         *)\n  \
+           ECHO_N='-n';;\n\
+         esac\n\
+         if test \"`echo -n x`\" = x; then :; fi\n\
+         x=$(echo -n foo)\n",
+    )?;
+
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--format", "json"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    let found = String::from_utf8(cmd.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<Vec<serde_json::Value>, _>>()?
+        .iter()
+        .map(|v| format!("{}:{}:{}", v["id"], v["line"], v["column"]))
+        .collect::<Vec<_>>();
+    assert_eq!(found, ["\"echo-flags\":15:10"]);
+    Ok(())
+}
+
+/*
+ * Makefile recipes are only checked with --experimental.
+ */
+#[test]
+fn check_portability_makefile() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(
+        dir.path().join("Makefile.in"),
+        "CC=\t@CC@\n\
+         all:\n\
+         \t@if [ \"$(CC)\" == gcc ]; then \\\n\
+         \t  echo $$RANDOM; \\\n\
+         \tfi\n\
+         \t${CC} ${CFLAGS:S/-O2//} -o $@\n",
+    )?;
+
+    let cmd = Command::new(MKTOOL)
+        .arg("check-portability")
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert!(cmd.stderr.is_empty());
+
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--experimental"])
+        .current_dir(dir.path())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(
+        stderr,
+        "WARNING: [check-portability] => Found $RANDOM:\n\
+         WARNING: [check-portability] Makefile.in:3: \
+         @if [ \"$(CC)\" == gcc ]; then echo $$RANDOM; fi\n\
+         ERROR: [check-portability] => Found test ... == ...:\n\
+         ERROR: [check-portability] Makefile.in:3: \
+         @if [ \"$(CC)\" == gcc ]; then echo $$RANDOM; fi\n"
+    );
+
+    /*
+     * Locations are those in the Makefile, not the shell command that make
+     * variables have been replaced in.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--experimental", "--format", "json"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    let found = String::from_utf8(cmd.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<Vec<serde_json::Value>, _>>()?
+        .iter()
+        .map(|v| format!("{}:{}:{}", v["id"], v["line"], v["column"]))
+        .collect::<Vec<_>>();
+    assert_eq!(found, ["\"random\":4:9", "\"test-eq\":3:16"]);
+    Ok(())
+}
+
+/*
+ * Files are scanned in parallel but output must always be sorted by path
+ * and line number.
+ */
+#[test]
+fn check_portability_sorted() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut expected = String::new();
+    for d in ["b", "a", "a/z", "c"] {
+        fs::create_dir_all(dir.path().join(d))?;
+    }
+    let mut paths = vec![];
+    for d in ["b", "a", "a/z", "c"] {
+        for f in 0..20 {
+            let path = format!("{d}/f{f:02}");
+            fs::write(
+                dir.path().join(&path),
+                "#!/bin/sh\necho $RANDOM\n\necho $RANDOM\n",
+            )?;
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    for path in paths {
+        for line in [2, 4] {
+            expected.push_str(&format!(
+                "WARNING: [check-portability] => Found $RANDOM:\n\
+                 WARNING: [check-portability] {path}:{line}: echo $RANDOM\n"
+            ));
+        }
+    }
+
+    for jobs in ["1", "8"] {
+        let cmd = Command::new(MKTOOL)
+            .args(["check-portability", "-j", jobs])
+            .current_dir(dir.path())
+            .output()?;
+        assert_eq!(cmd.status.code(), Some(0));
+        assert_eq!(String::from_utf8_lossy(&cmd.stderr), expected);
+    }
+    Ok(())
+}
+
+/*
+ * Inline and file suppressions, and reporting of unused suppressions.
+ */
+#[test]
+fn check_portability_suppressions() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let wrkdir = dir.path().join("work");
+    fs::create_dir(&wrkdir)?;
+    fs::write(
+        wrkdir.join("script"),
+        "#!/bin/sh\n\
+         [ a == b ] # mktool: ignore test-eq known to be harmless\n\
+         # mktool: ignore random,source\n\
+         echo $RANDOM\n\
+         [ c == d ]\n\
+         [ e == f ]\n\
+         echo ok # mktool: ignore bogus\n",
+    )?;
+
+    /*
+     * Generate a suppression for the remaining matches, then keep only the
+     * first so that the second is still reported.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--print-suppressions"])
+        .current_dir(&wrkdir)
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&cmd.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "# script:5: [ c == d ]");
+    assert!(lines[1].starts_with("script:test-eq:"));
+    let supp = dir.path().join("suppressions");
+    fs::write(
+        &supp,
+        format!(
+            "{} one-off\nscript:random unused\n",
+            lines[1].split('\t').next().ok_or("bad output")?
+        ),
+    )?;
+
+    let cmd = Command::new(MKTOOL)
+        .arg("check-portability")
+        .arg("--suppressions")
+        .arg(&supp)
+        .current_dir(&wrkdir)
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(
+        stderr,
+        format!(
+            "ERROR: [check-portability] => Found test ... == ...:\n\
+             ERROR: [check-portability] script:6: [ e == f ]\n\
+             WARNING: [check-portability] script:4: \
+             unused suppression for source\n\
+             WARNING: [check-portability] script:7: \
+             unknown check bogus in suppression\n\
+             WARNING: [check-portability] {}:2: \
+             unused suppression for random\n",
+            supp.display()
+        )
+    );
+    Ok(())
+}
+
+/*
+ * --fix rewrites "test ==" and writes new patches, or extends existing ones.
+ */
+#[test]
+fn check_portability_fix() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let wrksrc = dir.path().join("work");
+    let patchdir = dir.path().join("patches");
+    fs::create_dir_all(wrksrc.join("sub_dir"))?;
+    fs::create_dir(&patchdir)?;
+    fs::write(
+        wrksrc.join("sub_dir/build.sh"),
+        "#!/bin/sh\n[ \"$a\" == b ] && test c == \"==\"\necho $RANDOM\n",
+    )?;
+    fs::write(
+        wrksrc.join("configure"),
+        "#!/bin/sh\necho patched\nif [ x == y ]; then :; fi\n",
+    )?;
+    fs::write(
+        wrksrc.join("configure.orig"),
+        "#!/bin/sh\necho orig\nif [ x == y ]; then :; fi\n",
+    )?;
+    fs::write(
+        patchdir.join("patch-configure"),
+        "$NetBSD$\n\nExisting patch.\n\n\
+         --- configure.orig\n+++ configure\n\
+         @@ -1,3 +1,3 @@\n #!/bin/sh\n-echo orig\n+echo patched\n \
+         if [ x == y ]; then :; fi\n",
+    )?;
+
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--fix"])
+        .env("PATCHDIR", &patchdir)
+        .current_dir(&wrksrc)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&cmd.stdout);
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(
+        stdout.lines().filter(|l| l.contains("patch-")).collect::<Vec<_>>(),
+        [
+            patchdir.join("patch-configure").display().to_string(),
+            patchdir.join("patch-sub__dir_build.sh").display().to_string(),
+        ]
+    );
+    assert!(!stderr.contains("ERROR"));
+    assert!(stderr.contains("=> Found $RANDOM:"));
+
+    assert_eq!(
+        fs::read_to_string(wrksrc.join("sub_dir/build.sh"))?,
+        "#!/bin/sh\n[ \"$a\" = b ] && test c = \"==\"\necho $RANDOM\n",
+    );
+    assert!(wrksrc.join("sub_dir/build.sh.orig").exists());
+    assert_eq!(
+        fs::read_to_string(patchdir.join("patch-sub__dir_build.sh"))?,
+        "$NetBSD$\n\nUse portable shell syntax.\n\n\
+         --- sub_dir/build.sh.orig\n+++ sub_dir/build.sh\n\
+         @@ -1,3 +1,3 @@\n #!/bin/sh\n\
+         -[ \"$a\" == b ] && test c == \"==\"\n\
+         +[ \"$a\" = b ] && test c = \"==\"\n echo $RANDOM\n"
+    );
+    assert_eq!(
+        fs::read_to_string(patchdir.join("patch-configure"))?,
+        "$NetBSD$\n\nExisting patch.\n\n\
+         --- configure.orig\n+++ configure\n\
+         @@ -1,3 +1,3 @@\n #!/bin/sh\n-echo orig\n+echo patched\n\
+         -if [ x == y ]; then :; fi\n+if [ x = y ]; then :; fi\n"
+    );
+    assert_eq!(
+        fs::read_to_string(wrksrc.join("configure.orig"))?,
+        "#!/bin/sh\necho orig\nif [ x == y ]; then :; fi\n",
+    );
+
+    /*
+     * PATCHDIR is required.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--fix"])
+        .env_remove("PATCHDIR")
+        .current_dir(&wrksrc)
+        .output()?;
+    assert_ne!(cmd.status.code(), Some(0));
+    Ok(())
+}
+
+/*
+ * Files are checked based on their hashbang or name, or if listed in
+ * CHECK_PORTABILITY_SCRIPTS.
+ */
+#[test]
+fn check_portability_classify() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let files = [
+        ("configure", "[ a == b ]\n"),
+        ("env", "#!/usr/bin/env sh\n[ a == b ]\n"),
+        ("spaced", "#! /bin/sh -e\n[ a == b ]\n"),
+        ("usrbin", "#!/usr/bin/sh\n[ a == b ]\n"),
+        ("plain.sh", "[ a == b ]\n"),
+        ("bash.sh", "#!/bin/bash\n[ a == b ]\n"),
+        ("listed", "#!/bin/bash\n[ a == b ]\n"),
+        ("other", "[ a == b ]\n"),
+    ];
+    for (name, contents) in files {
+        fs::write(dir.path().join(name), contents)?;
+    }
+    let cmd = Command::new(MKTOOL)
+        .arg("check-portability")
+        .env("CHECK_PORTABILITY_SCRIPTS", "list*")
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    let found: Vec<&str> = stderr
+        .lines()
+        .filter_map(|l| l.strip_prefix("ERROR: [check-portability] "))
+        .filter(|l| !l.starts_with("=>"))
+        .filter_map(|l| l.split(':').next())
+        .collect();
+    assert_eq!(
+        found,
+        ["configure", "env", "listed", "plain.sh", "spaced", "usrbin"]
+    );
+    Ok(())
+}
+
+/*
+ * Machine-readable output formats.
+ */
+#[test]
+fn check_portability_format() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir(dir.path().join("a dir"))?;
+    fs::write(
+        dir.path().join("a dir/script"),
+        "#!/bin/sh\n  if [ \"\x1b[1m\" == b ]; then\n\techo $RANDOM\nfi\n",
+    )?;
+
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--format", "json"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    let stdout = String::from_utf8(cmd.stdout)?;
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<_, _>>()?;
+    assert_eq!(
+        lines,
+        [
+            serde_json::json!({
+                "id": "test-eq",
+                "severity": "error",
+                "path": "a dir/script",
+                "line": 2,
+                "column": 15,
+                "snippet": "if [ \"?[1m\" == b ]; then",
+            }),
+            serde_json::json!({
+                "id": "random",
+                "severity": "warning",
+                "path": "a dir/script",
+                "line": 3,
+                "column": 7,
+                "snippet": "echo $RANDOM",
+            }),
+        ]
+    );
+    assert!(!String::from_utf8_lossy(&cmd.stderr).contains("Found"));
+
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--format", "sarif"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    let sarif: serde_json::Value = serde_json::from_slice(&cmd.stdout)?;
+    assert_eq!(sarif["version"], "2.1.0");
+    let run = &sarif["runs"][0];
+    let results = run["results"].as_array().ok_or("no results")?;
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["ruleId"], "test-eq");
+    assert_eq!(results[0]["level"], "error");
+    let index = results[0]["ruleIndex"].as_u64().ok_or("no index")?;
+    assert_eq!(run["tool"]["driver"]["rules"][index as usize]["id"], "test-eq");
+    let loc = &results[0]["locations"][0]["physicalLocation"];
+    assert_eq!(loc["artifactLocation"]["uri"], "a%20dir/script");
+    assert_eq!(loc["region"]["startLine"], 2);
+    assert_eq!(loc["region"]["startColumn"], 15);
+    assert_eq!(results[1]["level"], "warning");
+
+    /*
+     * With --fix the patches written are printed to stderr so that stdout
+     * can still be parsed.
+     */
+    let patchdir = dir.path().join("patches");
+    fs::create_dir(&patchdir)?;
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "--fix", "--format", "json"])
+        .arg("-P")
+        .arg(&patchdir)
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    let stdout = String::from_utf8(cmd.stdout)?;
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<_, _>>()?;
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["id"], "random");
+    let patch = patchdir.join("patch-a dir_script");
+    assert!(patch.exists());
+    assert!(
+        String::from_utf8(cmd.stderr)?
+            .lines()
+            .any(|l| l == patch.display().to_string())
+    );
+    Ok(())
+}
+
+/*
+ * A ".in" file is only skipped if the generated file at the same path is
+ * patched, looking at every file in each patch.
+ */
+#[test]
+fn check_portability_patched() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let wrksrc = dir.path().join("work");
+    let patchdir = dir.path().join("patches");
+    for d in ["sub", "other", "lib"] {
+        fs::create_dir_all(wrksrc.join(d))?;
+    }
+    fs::create_dir(&patchdir)?;
+    for f in ["sub/configure.in", "other/configure.in", "lib/x.sh.in"] {
+        fs::write(wrksrc.join(f), "#!/bin/sh\n[ a == b ]\n")?;
+    }
+    fs::write(
+        patchdir.join("patch-sub"),
+        "$NetBSD$\n\n\
+         --- sub/configure.orig\t2024-01-01 00:00:00\n\
+         +++ sub/configure\t2024-01-01 00:00:00\n\
+         @@ -1 +1 @@\n-a\n+b\n\
+         --- a/lib/x.sh\n+++ b/lib/x.sh\n\
+         @@ -1 +1 @@\n-a\n+b\n",
+    )?;
+    let cmd = Command::new(MKTOOL)
+        .arg("check-portability")
+        .env("PATCHDIR", &patchdir)
+        .current_dir(&wrksrc)
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    let found: Vec<&str> = stderr
+        .lines()
+        .filter_map(|l| l.strip_prefix("ERROR: [check-portability] "))
+        .filter(|l| !l.starts_with("=>"))
+        .filter_map(|l| l.split(':').next())
+        .collect();
+    assert_eq!(found, ["other/configure.in"]);
+    Ok(())
+}
+
+/*
+ * The directory, files, and settings can all be passed as arguments rather
+ * than relying on the current directory and environment.
+ */
+#[test]
+fn check_portability_args() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let wrksrc = dir.path().join("work");
+    let patchdir = dir.path().join("patches");
+    fs::create_dir_all(wrksrc.join("sub"))?;
+    fs::create_dir(&patchdir)?;
+    for f in ["a.sh", "b.sh", "sub/c.sh", "sub/d.sh", "configure.in"] {
+        fs::write(wrksrc.join(f), "#!/bin/sh\n[ a == b ]\n")?;
+    }
+    fs::write(
+        patchdir.join("patch-configure"),
+        "--- configure.orig\n+++ configure\n",
+    )?;
+
+    let found = |args: &[&str], stdin: &str| -> Result<Vec<String>> {
+        let mut cmd = Command::new(MKTOOL)
+            .arg("check-portability")
+            .arg("-d")
+            .arg(&wrksrc)
+            .args(args)
+            .env_remove("PATCHDIR")
+            .env_remove("CHECK_PORTABILITY_SKIP")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        cmd.stdin.take().ok_or("no stdin")?.write_all(stdin.as_bytes())?;
+        let output = cmd.wait_with_output()?;
+        Ok(String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter_map(|l| l.strip_prefix("ERROR: [check-portability] "))
+            .filter(|l| !l.starts_with("=>"))
+            .filter_map(|l| l.split(':').next())
+            .map(String::from)
+            .collect())
+    };
+
+    assert_eq!(
+        found(&[], "")?,
+        ["a.sh", "b.sh", "configure.in", "sub/c.sh", "sub/d.sh"]
+    );
+    assert_eq!(found(&["sub", "a.sh"], "")?, ["a.sh", "sub/c.sh", "sub/d.sh"]);
+    assert_eq!(
+        found(&["-I", "-", "b.sh"], "sub/d.sh\na.sh\n")?,
+        ["a.sh", "b.sh", "sub/d.sh"]
+    );
+    assert_eq!(
+        found(&["--skip", "sub/*", "--skip", "a.*"], "")?,
+        ["b.sh", "configure.in"]
+    );
+    let p = patchdir.to_string_lossy();
+    assert_eq!(found(&["-P", &p, "--skip", "sub/*"], "")?, ["a.sh", "b.sh"]);
+
+    /*
+     * Missing files are an error.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["check-portability", "-d"])
+        .arg(&wrksrc)
+        .arg("nonexistent")
+        .output()?;
+    assert_ne!(cmd.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&cmd.stderr).contains("nonexistent"));
+    Ok(())
+}
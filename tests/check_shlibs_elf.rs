@@ -0,0 +1,699 @@
+#![cfg(all(unix, not(target_os = "macos")))]
+/*
+ * Copyright (c) 2026 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+const MKTOOL: &str = env!("CARGO_BIN_EXE_mktool");
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/*
+ * ELF constants from <elf.h>.
+ */
+const ET_DYN: u16 = 3;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_SYMENT: u64 = 11;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+const SHT_GNU_VERDEF: u32 = 0x6fff_fffd;
+const SHT_GNU_VERNEED: u32 = 0x6fff_fffe;
+const SHT_GNU_VERSYM: u32 = 0x6fff_ffff;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+const STT_FUNC: u8 = 2;
+const SHN_ABS: u16 = 0xfff1;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+
+fn push_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+/*
+ * Dynamic section contents for a test object.  Defined symbols may have a
+ * version, and undefined symbols may have a (file, version) requirement.
+ */
+#[derive(Default)]
+struct Dynamic<'a> {
+    needed: &'a [&'a str],
+    rpath: Option<&'a str>,
+    runpath: Option<&'a str>,
+    defined: &'a [(&'a str, Option<&'a str>)],
+    undefined: &'a [(&'a str, Option<(&'a str, &'a str)>)],
+    weak: &'a [&'a str],
+}
+
+/*
+ * Build a minimal ELF64 shared object that goblin can parse.  There is a
+ * single PT_LOAD covering the whole file (so that virtual addresses are file
+ * offsets) and a PT_DYNAMIC pointing at the dynamic array.  The string table
+ * immediately follows the program headers, then the symbol table and a
+ * SysV hash table (only used for the symbol count).
+ *
+ * Section headers are only written if there are symbol versions, as goblin
+ * finds the .gnu.version sections that way.
+ */
+fn build_elf(dynamic: &Dynamic) -> Vec<u8> {
+    let mut strtab: Vec<u8> = vec![0];
+    let mut add_str = |s: &str| -> u32 {
+        let off = strtab.len() as u32;
+        strtab.extend_from_slice(s.as_bytes());
+        strtab.push(0);
+        off
+    };
+    let mut dyns: Vec<(u64, u64)> = vec![];
+    for lib in dynamic.needed {
+        dyns.push((DT_NEEDED, add_str(lib).into()));
+    }
+    if let Some(rpath) = dynamic.rpath {
+        dyns.push((DT_RPATH, add_str(rpath).into()));
+    }
+    if let Some(runpath) = dynamic.runpath {
+        dyns.push((DT_RUNPATH, add_str(runpath).into()));
+    }
+
+    /*
+     * Assign version indices, starting at 2 as 0 and 1 are reserved for
+     * local and global symbols.  Definitions come first, then requirements
+     * grouped by file.
+     */
+    let mut verdefs: Vec<(&str, u16)> = vec![];
+    for (_, ver) in dynamic.defined {
+        if let Some(ver) = ver {
+            if !verdefs.iter().any(|(v, _)| v == ver) {
+                verdefs.push((ver, verdefs.len() as u16 + 2));
+            }
+        }
+    }
+    let mut verneeds: Vec<(&str, Vec<(&str, u16)>)> = vec![];
+    let mut nextver = verdefs.len() as u16 + 2;
+    for (_, req) in dynamic.undefined {
+        let Some((file, ver)) = req else { continue };
+        let idx = match verneeds.iter().position(|(f, _)| f == file) {
+            Some(idx) => idx,
+            None => {
+                verneeds.push((file, vec![]));
+                verneeds.len() - 1
+            }
+        };
+        if !verneeds[idx].1.iter().any(|(v, _)| v == ver) {
+            verneeds[idx].1.push((ver, nextver));
+            nextver += 1;
+        }
+    }
+    let versioned = !verdefs.is_empty() || !verneeds.is_empty();
+
+    /*
+     * Symbol table entries of (name, bind, shndx, version index).
+     */
+    let mut syms: Vec<(u32, u8, u16, u16)> = vec![(0, 0, 0, 0)];
+    for (name, ver) in dynamic.defined {
+        let vidx = match ver {
+            Some(ver) => verdefs.iter().find(|(v, _)| v == ver).map(|v| v.1),
+            None => None,
+        };
+        syms.push((add_str(name), STB_GLOBAL, SHN_ABS, vidx.unwrap_or(1)));
+    }
+    for (name, req) in dynamic.undefined {
+        let mut vidx = 1;
+        if let Some((file, ver)) = req {
+            for (f, vers) in &verneeds {
+                if f == file {
+                    for (v, idx) in vers {
+                        if v == ver {
+                            vidx = *idx;
+                        }
+                    }
+                }
+            }
+        }
+        syms.push((add_str(name), STB_GLOBAL, 0, vidx));
+    }
+    for name in dynamic.weak {
+        syms.push((add_str(name), STB_WEAK, 0, 1));
+    }
+    let verdef_names: Vec<u32> =
+        verdefs.iter().map(|(v, _)| add_str(v)).collect();
+    let verneed_names: Vec<(u32, Vec<u32>)> = verneeds
+        .iter()
+        .map(|(f, vers)| {
+            (add_str(f), vers.iter().map(|(v, _)| add_str(v)).collect())
+        })
+        .collect();
+
+    let strtab_off = EHDR_SIZE + 2 * PHDR_SIZE;
+    let sym_off = (strtab_off + strtab.len()).next_multiple_of(8);
+    let hash_off = sym_off + syms.len() * SYM_SIZE;
+    let versym_off = hash_off + (3 + syms.len()) * 4;
+    let verdef_off = (versym_off + syms.len() * 2).next_multiple_of(4);
+    let verneed_off = verdef_off + verdefs.len() * 28;
+    let verneed_size: usize =
+        verneeds.iter().map(|(_, vers)| 16 + 16 * vers.len()).sum();
+    let dyn_off = (verneed_off + verneed_size).next_multiple_of(8);
+    dyns.push((DT_HASH, hash_off as u64));
+    dyns.push((DT_SYMTAB, sym_off as u64));
+    dyns.push((DT_SYMENT, SYM_SIZE as u64));
+    dyns.push((DT_STRTAB, strtab_off as u64));
+    dyns.push((DT_STRSZ, strtab.len() as u64));
+    dyns.push((DT_NULL, 0));
+    let dyn_size = dyns.len() * 16;
+    let shdr_off = dyn_off + dyn_size;
+    let mut shdrs: Vec<(u32, usize, usize, usize)> = vec![];
+    if versioned {
+        shdrs.push((0, 0, 0, 0));
+        shdrs.push((SHT_GNU_VERSYM, versym_off, syms.len() * 2, 0));
+        if !verdefs.is_empty() {
+            shdrs.push((
+                SHT_GNU_VERDEF,
+                verdef_off,
+                verdefs.len() * 28,
+                verdefs.len(),
+            ));
+        }
+        if !verneeds.is_empty() {
+            shdrs.push((
+                SHT_GNU_VERNEED,
+                verneed_off,
+                verneed_size,
+                verneeds.len(),
+            ));
+        }
+    }
+    let total = shdr_off + shdrs.len() * SHDR_SIZE;
+
+    let mut elf: Vec<u8> = Vec::with_capacity(total);
+    elf.extend_from_slice(b"\x7fELF");
+    elf.extend_from_slice(&[2, 1, 1, 0]); /* 64-bit, LSB, version, SysV */
+    elf.resize(16, 0);
+    push_u16(&mut elf, ET_DYN);
+    push_u16(&mut elf, EM_X86_64);
+    push_u32(&mut elf, 1); /* e_version */
+    push_u64(&mut elf, 0); /* e_entry */
+    push_u64(&mut elf, EHDR_SIZE as u64); /* e_phoff */
+    push_u64(&mut elf, if versioned { shdr_off as u64 } else { 0 });
+    push_u32(&mut elf, 0); /* e_flags */
+    push_u16(&mut elf, EHDR_SIZE as u16);
+    push_u16(&mut elf, PHDR_SIZE as u16);
+    push_u16(&mut elf, 2); /* e_phnum */
+    push_u16(&mut elf, SHDR_SIZE as u16);
+    push_u16(&mut elf, shdrs.len() as u16); /* e_shnum */
+    push_u16(&mut elf, 0); /* e_shstrndx */
+
+    for (ptype, off, size, align) in
+        [(PT_LOAD, 0, total, 0x1000), (PT_DYNAMIC, dyn_off, dyn_size, 8)]
+    {
+        push_u32(&mut elf, ptype);
+        push_u32(&mut elf, 4); /* PF_R */
+        push_u64(&mut elf, off as u64); /* p_offset */
+        push_u64(&mut elf, off as u64); /* p_vaddr */
+        push_u64(&mut elf, off as u64); /* p_paddr */
+        push_u64(&mut elf, size as u64); /* p_filesz */
+        push_u64(&mut elf, size as u64); /* p_memsz */
+        push_u64(&mut elf, align);
+    }
+
+    elf.extend_from_slice(&strtab);
+    elf.resize(sym_off, 0);
+    for (name, bind, shndx, _) in &syms {
+        push_u32(&mut elf, *name);
+        elf.push(if *name == 0 { 0 } else { (bind << 4) | STT_FUNC });
+        elf.push(0); /* st_other */
+        push_u16(&mut elf, *shndx);
+        push_u64(&mut elf, 0); /* st_value */
+        push_u64(&mut elf, 0); /* st_size */
+    }
+
+    /*
+     * A single hash bucket with empty chains, nchain is the symbol count.
+     */
+    push_u32(&mut elf, 1);
+    push_u32(&mut elf, syms.len() as u32);
+    elf.resize(versym_off, 0);
+
+    for (_, _, _, vidx) in &syms {
+        push_u16(&mut elf, *vidx);
+    }
+    elf.resize(verdef_off, 0);
+    for (i, ((_, idx), name)) in verdefs.iter().zip(&verdef_names).enumerate() {
+        let last = i == verdefs.len() - 1;
+        push_u16(&mut elf, 1); /* vd_version */
+        push_u16(&mut elf, 0); /* vd_flags */
+        push_u16(&mut elf, *idx); /* vd_ndx */
+        push_u16(&mut elf, 1); /* vd_cnt */
+        push_u32(&mut elf, 0); /* vd_hash */
+        push_u32(&mut elf, 20); /* vd_aux */
+        push_u32(&mut elf, if last { 0 } else { 28 }); /* vd_next */
+        push_u32(&mut elf, *name); /* vda_name */
+        push_u32(&mut elf, 0); /* vda_next */
+    }
+    for (i, ((_, vers), (file, names))) in
+        verneeds.iter().zip(&verneed_names).enumerate()
+    {
+        let last = i == verneeds.len() - 1;
+        push_u16(&mut elf, 1); /* vn_version */
+        push_u16(&mut elf, vers.len() as u16); /* vn_cnt */
+        push_u32(&mut elf, *file); /* vn_file */
+        push_u32(&mut elf, 16); /* vn_aux */
+        let next = if last { 0 } else { 16 + 16 * vers.len() };
+        push_u32(&mut elf, next as u32); /* vn_next */
+        for (j, ((_, idx), name)) in vers.iter().zip(names).enumerate() {
+            push_u32(&mut elf, 0); /* vna_hash */
+            push_u16(&mut elf, 0); /* vna_flags */
+            push_u16(&mut elf, *idx); /* vna_other */
+            push_u32(&mut elf, *name); /* vna_name */
+            let next = if j == vers.len() - 1 { 0 } else { 16 };
+            push_u32(&mut elf, next); /* vna_next */
+        }
+    }
+
+    elf.resize(dyn_off, 0);
+    for (tag, val) in dyns {
+        push_u64(&mut elf, tag);
+        push_u64(&mut elf, val);
+    }
+    for (shtype, off, size, info) in shdrs {
+        push_u32(&mut elf, 0); /* sh_name */
+        push_u32(&mut elf, shtype);
+        push_u64(&mut elf, 0); /* sh_flags */
+        push_u64(&mut elf, off as u64); /* sh_addr */
+        push_u64(&mut elf, off as u64); /* sh_offset */
+        push_u64(&mut elf, size as u64); /* sh_size */
+        push_u32(&mut elf, 0); /* sh_link */
+        push_u32(&mut elf, info as u32); /* sh_info */
+        push_u64(&mut elf, 0); /* sh_addralign */
+        push_u64(&mut elf, 0); /* sh_entsize */
+    }
+    elf
+}
+
+fn run_check_shlibs(
+    testdir: &Path,
+    input: &str,
+    args: &[&str],
+    extra_env: &[(&str, &str)],
+) -> Result<Output> {
+    let destdir = testdir.join("destdir");
+    let wrkdir = testdir.join("wrkdir");
+    let depends = testdir.join("depends");
+    fs::create_dir_all(&destdir)?;
+    fs::create_dir_all(&wrkdir)?;
+    if !depends.exists() {
+        fs::write(&depends, "")?;
+    }
+
+    let mut builder = Command::new(MKTOOL);
+    builder
+        .arg("check-shlibs")
+        .args(args)
+        .env("DESTDIR", &destdir)
+        .env("CROSS_DESTDIR", "")
+        .env("WRKDIR", &wrkdir)
+        .env("PKG_ADMIN_CMD", "/usr/bin/true")
+        .env("DEPENDS_FILE", &depends)
+        .env_remove("PLATFORM_RPATH")
+        .env_remove("CHECK_SHLIBS_LIB")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    for (k, v) in extra_env {
+        builder.env(k, v);
+    }
+    let mut cmd = builder.spawn()?;
+
+    let mut stdin = cmd.stdin.take().ok_or("failed to open stdin")?;
+    stdin.write_all(input.as_bytes())?;
+    drop(stdin);
+    Ok(cmd.wait_with_output()?)
+}
+
+/*
+ * Objects are checked in parallel but output is always in input order,
+ * including errors for objects that cannot be read.
+ */
+#[test]
+fn test_output_order() -> Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let testdir = tmpdir.path();
+
+    let mut input = String::new();
+    let mut expected = vec![];
+    let mut expected_err = vec![];
+    for i in 0..100 {
+        let obj = testdir.join(format!("obj{i}"));
+        if i % 10 == 0 {
+            expected_err.push(format!(
+                "{}: No such file or directory (os error 2)",
+                obj.display()
+            ));
+        } else {
+            let libs = [format!("liba{i}.so"), format!("libb{i}.so")];
+            let needed: Vec<&str> = libs.iter().map(|s| s.as_str()).collect();
+            fs::write(
+                &obj,
+                build_elf(&Dynamic { needed: &needed, ..Default::default() }),
+            )?;
+            for lib in libs {
+                expected
+                    .push(format!("{}: missing library: {lib}", obj.display()));
+            }
+        }
+        input.push_str(&format!("{}\n", obj.display()));
+    }
+
+    for jobs in ["1", "8"] {
+        let out = run_check_shlibs(testdir, &input, &["-j", jobs], &[])?;
+        assert_eq!(out.status.code(), Some(0));
+        let stdout = String::from_utf8(out.stdout)?;
+        let stderr = String::from_utf8(out.stderr)?;
+        assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+        assert_eq!(stderr.lines().collect::<Vec<_>>(), expected_err);
+    }
+    Ok(())
+}
+
+/*
+ * Libraries are searched for in DESTDIR, then RUNPATH/RPATH, then
+ * PLATFORM_RPATH, and checks are run against those found.
+ */
+#[test]
+fn test_search_order() -> Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let testdir = tmpdir.path();
+    let wrkdir = testdir.join("wrkdir");
+    let syslib = testdir.join("syslib");
+    fs::create_dir_all(testdir.join("destdir/opt/lib"))?;
+    fs::create_dir_all(wrkdir.join("lib"))?;
+    fs::create_dir_all(&syslib)?;
+    fs::write(testdir.join("destdir/opt/lib/libstaged.so"), b"")?;
+    fs::write(wrkdir.join("lib/libwrk.so"), b"")?;
+    fs::write(syslib.join("libc.so"), b"")?;
+
+    let rpath = format!("/opt/lib:{}", wrkdir.join("lib").display());
+    let obj = testdir.join("obj");
+    fs::write(
+        &obj,
+        build_elf(&Dynamic {
+            needed: &["libstaged.so", "libwrk.so", "libc.so", "libnope.so"],
+            runpath: Some(&rpath),
+            ..Default::default()
+        }),
+    )?;
+    let out = run_check_shlibs(
+        testdir,
+        &format!("{}\n", obj.display()),
+        &[],
+        &[("PLATFORM_RPATH", &syslib.to_string_lossy())],
+    )?;
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8(out.stdout)?;
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            format!(
+                "{}: path relative to WRKDIR: {}",
+                obj.display(),
+                wrkdir.join("lib/libwrk.so").display()
+            ),
+            format!("{}: missing library: libnope.so", obj.display()),
+        ]
+    );
+    Ok(())
+}
+
+/*
+ * Dynamic string tokens in RUNPATH are expanded relative to where the object
+ * will be installed, so "$ORIGIN/../lib" finds libraries in DESTDIR.  $LIB is
+ * only expanded if CHECK_SHLIBS_LIB is set.
+ */
+#[test]
+fn test_dst_expansion() -> Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let testdir = tmpdir.path();
+    let destdir = testdir.join("destdir");
+    fs::create_dir_all(destdir.join("opt/bin"))?;
+    fs::create_dir_all(destdir.join("opt/lib"))?;
+    fs::create_dir_all(destdir.join("opt/lib64/x86_64"))?;
+    fs::write(destdir.join("opt/lib/liborigin.so"), b"")?;
+    fs::write(destdir.join("opt/lib64/x86_64/libplat.so"), b"")?;
+
+    let obj = destdir.join("opt/bin/prog");
+    fs::write(
+        &obj,
+        build_elf(&Dynamic {
+            needed: &["liborigin.so", "libplat.so", "libnope.so"],
+            runpath: Some("$ORIGIN/../lib:${ORIGIN}/../$LIB/${PLATFORM}"),
+            ..Default::default()
+        }),
+    )?;
+    /*
+     * An RPATH entry with an unknown token is ignored.
+     */
+    let obj2 = destdir.join("opt/bin/prog2");
+    fs::write(
+        &obj2,
+        build_elf(&Dynamic {
+            needed: &["liborigin.so"],
+            rpath: Some("$UNKNOWN/../lib"),
+            ..Default::default()
+        }),
+    )?;
+
+    let input = format!("{}\n{}\n", obj.display(), obj2.display());
+    let env = [("CHECK_SHLIBS_LIB", "lib64")];
+    let out = run_check_shlibs(testdir, &input, &[], &env)?;
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8(out.stdout)?;
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            format!("{}: missing library: libnope.so", obj.display()),
+            format!("{}: missing library: liborigin.so", obj2.display()),
+        ]
+    );
+    assert!(out.stderr.is_empty());
+
+    let out = run_check_shlibs(testdir, &input, &[], &[])?;
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8(out.stdout)?;
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            format!("{}: missing library: libplat.so", obj.display()),
+            format!("{}: missing library: libnope.so", obj.display()),
+            format!("{}: missing library: liborigin.so", obj2.display()),
+        ]
+    );
+    assert_eq!(
+        String::from_utf8(out.stderr)?,
+        "WARNING: $LIB in RPATH or RUNPATH not expanded, \
+         set CHECK_SHLIBS_LIB\n"
+    );
+    Ok(())
+}
+
+/*
+ * With --symbols, undefined symbols must be provided by a dependency, and
+ * versioned references must be satisfied by the library they name.
+ */
+#[test]
+fn test_undefined_symbols() -> Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let testdir = tmpdir.path();
+    let destdir = testdir.join("destdir");
+    fs::create_dir_all(destdir.join("opt/lib"))?;
+    fs::write(
+        destdir.join("opt/lib/libfoo.so.1"),
+        build_elf(&Dynamic {
+            defined: &[
+                ("foo", None),
+                ("foo_v1", Some("FOO_1.0")),
+                ("foo_v2", Some("FOO_1.0")),
+            ],
+            ..Default::default()
+        }),
+    )?;
+    fs::write(
+        destdir.join("opt/lib/libbar.so.1"),
+        build_elf(&Dynamic { defined: &[("bar", None)], ..Default::default() }),
+    )?;
+    fs::write(destdir.join("opt/lib/libempty.so.1"), b"")?;
+
+    let obj = destdir.join("opt/bin/prog");
+    fs::create_dir_all(destdir.join("opt/bin"))?;
+    fs::write(
+        &obj,
+        build_elf(&Dynamic {
+            needed: &["libfoo.so.1", "libbar.so.1"],
+            runpath: Some("/opt/lib"),
+            undefined: &[
+                ("foo", None),
+                ("bar", None),
+                ("foo_v1", Some(("libfoo.so.1", "FOO_1.0"))),
+                ("foo_v2", Some(("libfoo.so.1", "FOO_2.0"))),
+                ("bar", Some(("libfoo.so.1", "FOO_1.0"))),
+                ("nope", None),
+            ],
+            weak: &["__gmon_start__"],
+            ..Default::default()
+        }),
+    )?;
+    /*
+     * Nothing can be said if a dependency cannot be parsed.
+     */
+    let obj2 = destdir.join("opt/bin/prog2");
+    fs::write(
+        &obj2,
+        build_elf(&Dynamic {
+            needed: &["libfoo.so.1", "libempty.so.1"],
+            runpath: Some("/opt/lib"),
+            undefined: &[("nope", None)],
+            ..Default::default()
+        }),
+    )?;
+
+    let input = format!("{}\n{}\n", obj.display(), obj2.display());
+    let out = run_check_shlibs(testdir, &input, &[], &[])?;
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+
+    let out = run_check_shlibs(testdir, &input, &["--symbols"], &[])?;
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8(out.stdout)?;
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            format!("{}: undefined symbol: foo_v2@FOO_2.0", obj.display()),
+            format!("{}: undefined symbol: bar@FOO_1.0", obj.display()),
+            format!("{}: undefined symbol: nope", obj.display()),
+        ]
+    );
+    Ok(())
+}
+
+/*
+ * With --unused, libraries that no undefined symbol binds to are reported,
+ * along with the package that owns them.  Symbols bind to the first library
+ * that defines them unless a version names a specific library.
+ */
+#[test]
+fn test_unused_libraries() -> Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    let testdir = tmpdir.path();
+    let destdir = testdir.join("destdir");
+    let pkglib = testdir.join("pkg/lib");
+    fs::create_dir_all(destdir.join("opt/lib"))?;
+    fs::create_dir_all(&pkglib)?;
+    fs::write(
+        destdir.join("opt/lib/libver.so.1"),
+        build_elf(&Dynamic {
+            defined: &[("foo_v1", Some("FOO_1.0"))],
+            ..Default::default()
+        }),
+    )?;
+    fs::write(
+        destdir.join("opt/lib/libfoo.so.1"),
+        build_elf(&Dynamic {
+            defined: &[("foo", None), ("foo_v1", Some("FOO_1.0"))],
+            ..Default::default()
+        }),
+    )?;
+    fs::write(
+        pkglib.join("libbar.so.1"),
+        build_elf(&Dynamic {
+            defined: &[("foo", None), ("bar", None)],
+            ..Default::default()
+        }),
+    )?;
+    fs::write(
+        pkglib.join("libbaz.so.1"),
+        build_elf(&Dynamic { defined: &[("baz", None)], ..Default::default() }),
+    )?;
+
+    /*
+     * A pkg_admin that reports libbaz as belonging to a package.
+     */
+    let pkg_admin = testdir.join("pkg_admin");
+    fs::write(
+        &pkg_admin,
+        format!(
+            "#!/bin/sh\necho 'file: {} pkg: baz-1.0'\n",
+            pkglib.join("libbaz.so.1").display()
+        ),
+    )?;
+    fs::set_permissions(&pkg_admin, fs::Permissions::from_mode(0o755))?;
+
+    let obj = destdir.join("opt/bin/prog");
+    fs::create_dir_all(destdir.join("opt/bin"))?;
+    fs::write(
+        &obj,
+        build_elf(&Dynamic {
+            needed: &[
+                "libver.so.1",
+                "libfoo.so.1",
+                "libbar.so.1",
+                "libbaz.so.1",
+            ],
+            runpath: Some(&format!("/opt/lib:{}", pkglib.display())),
+            undefined: &[
+                ("foo", None),
+                ("foo_v1", Some(("libfoo.so.1", "FOO_1.0"))),
+            ],
+            weak: &["bar"],
+            ..Default::default()
+        }),
+    )?;
+
+    let out = run_check_shlibs(
+        testdir,
+        &format!("{}\n", obj.display()),
+        &["--unused"],
+        &[("PKG_ADMIN_CMD", &pkg_admin.to_string_lossy())],
+    )?;
+    assert_eq!(out.status.code(), Some(0));
+    let stdout = String::from_utf8(out.stdout)?;
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            format!("{}: unused library: libver.so.1", obj.display()),
+            format!("{}: unused library: libbaz.so.1 (baz-1.0)", obj.display()),
+        ]
+    );
+    Ok(())
+}
@@ -469,6 +469,69 @@ fn test_checksum_bad_algorithm() -> Result<()> {
     Ok(())
 }
 
+/*
+ * Checksums using algorithms that pkgsrc::digest does not support are
+ * verified like any other.
+ */
+#[test]
+fn test_checksum_new_algorithms() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let distinfo = dir.path().join("distinfo");
+    let blake3 =
+        "9df920192a5c84e2e75a97bb4a7adc475f7be49ced71d756027c8fbb9f1ec30b";
+    let contents = format!(
+        "$NetBSD$\n\n{}\n{}\n{}\n{}\n",
+        format_args!("BLAKE3 (digest1.txt) = {blake3}"),
+        "SHA3-256 (digest1.txt) = f67b5bfbba6330577590a9d36a54a66ea24ee9bac5c4778cae6aa1b0ce46594a",
+        "Size (digest1.txt) = 159 bytes",
+        "SHA3-256 (patch-Makefile) = 5c8a0b506934f218a8f8e87ef5297b0bbee72c5b5788e89785d49191a5785742",
+    );
+    fs::write(&distinfo, &contents)?;
+
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg(&distinfo)
+        .arg("digest1.txt")
+        .current_dir("tests/data")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(
+        cmd.stdout,
+        "=> Checksum BLAKE3 OK for digest1.txt\n\
+         => Checksum SHA3-256 OK for digest1.txt\n"
+            .as_bytes()
+    );
+    assert_eq!(cmd.stderr, "".as_bytes());
+
+    let cmd = Command::new(MKTOOL)
+        .args(["checksum", "-a", "sha3-256", "-p"])
+        .arg(&distinfo)
+        .arg("patch-Makefile")
+        .current_dir("tests/data")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(
+        cmd.stdout,
+        "=> Checksum SHA3-256 OK for patch-Makefile\n".as_bytes()
+    );
+    assert_eq!(cmd.stderr, "".as_bytes());
+
+    fs::write(&distinfo, contents.replace(blake3, &"0".repeat(64)))?;
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg(&distinfo)
+        .arg("digest1.txt")
+        .current_dir("tests/data")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(cmd.stdout, "".as_bytes());
+    assert_eq!(
+        cmd.stderr,
+        "checksum: Checksum BLAKE3 mismatch for digest1.txt\n".as_bytes()
+    );
+    Ok(())
+}
+
 /*
  * Verify -j flag is accepted.
  */
@@ -660,3 +723,344 @@ fn test_checksum_mismatch() -> Result<()> {
     assert_eq!(cmd.stderr, output.as_bytes());
     Ok(())
 }
+
+/*
+ * Verify every distinfo entry against a distdir and patchdir, including
+ * DIST_SUBDIR entries.  All problems should be reported in one run, with
+ * mismatches and missing files taking priority for the exit status.
+ */
+#[test]
+fn test_checksum_all() -> Result<()> {
+    let mut data = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    data.push("tests/data");
+    let tmpdir = tempfile::tempdir()?;
+    let distdir = tmpdir.path().join("distfiles");
+    let patchdir = tmpdir.path().join("patches");
+    fs::create_dir_all(distdir.join("sub"))?;
+    fs::create_dir_all(&patchdir)?;
+    fs::copy(data.join("digest1.txt"), distdir.join("digest1.txt"))?;
+    fs::copy(data.join("digest2.txt"), distdir.join("sub/digest2.txt"))?;
+    fs::copy(data.join("patch-Makefile"), patchdir.join("patch-Makefile"))?;
+    fs::write(distdir.join("sub/stale.tar.gz"), "stale\n")?;
+    fs::write(distdir.join("unrelated.tar.gz"), "shared\n")?;
+    fs::write(patchdir.join("patch-extra"), "extra\n")?;
+
+    let distinfo = tmpdir.path().join("distinfo");
+    let orig = fs::read_to_string(data.join("distinfo"))?;
+    let mut contents = orig.replace("(digest2.txt)", "(sub/digest2.txt)");
+    contents.push_str("SHA1 (sub/gone.tar.gz) = 1234\n");
+    contents.push_str("SHA1 (patch-gone) = 1234\n");
+    fs::write(&distinfo, &contents)?;
+
+    let output = format!(
+        "{}\n{}\n{}\n{}\n{}\n",
+        "=> Checksum BLAKE2s OK for digest1.txt",
+        "=> Checksum SHA512 OK for digest1.txt",
+        "=> Checksum SHA1 OK for patch-Makefile",
+        "=> Checksum BLAKE2s OK for sub/digest2.txt",
+        "=> Checksum SHA512 OK for sub/digest2.txt",
+    );
+    let outerr = format!(
+        "{}\n{}\n{}\n{}\n",
+        "checksum: No checksum recorded for patch-extra",
+        "checksum: No checksum recorded for sub/stale.tar.gz",
+        "checksum: Missing file patch-gone",
+        "checksum: Missing file sub/gone.tar.gz",
+    );
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--all")
+        .arg("-d")
+        .arg(&distdir)
+        .arg("-P")
+        .arg(&patchdir)
+        .arg(&distinfo)
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&cmd.stdout), output);
+    assert_eq!(String::from_utf8_lossy(&cmd.stderr), outerr);
+
+    /*
+     * Missing files use the name recorded in distinfo, as for every other
+     * status.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["checksum", "--all", "--format", "json", "-d"])
+        .arg(&distdir)
+        .arg("-P")
+        .arg(&patchdir)
+        .arg(&distinfo)
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    let missing: Vec<String> = String::from_utf8(cmd.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<Vec<serde_json::Value>, _>>()?
+        .into_iter()
+        .filter(|v| v["status"] == "missing")
+        .map(|v| v["filename"].to_string())
+        .collect();
+    assert_eq!(missing, ["\"patch-gone\"", "\"sub/gone.tar.gz\""]);
+
+    /*
+     * Only unrecorded files is exit 2, and every mismatch is reported
+     * rather than stopping at the first.
+     */
+    fs::write(&distinfo, orig.replace("(digest2.txt)", "(sub/digest2.txt)"))?;
+    fs::write(
+        patchdir.join("patch-Makefile"),
+        fs::read_to_string(data.join("patch-Makefile"))? + "changed\n",
+    )?;
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--all")
+        .arg("-P")
+        .arg(&patchdir)
+        .arg(&distinfo)
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(cmd.stdout, "".as_bytes());
+    assert_eq!(lines.len(), 4, "unexpected output: {stderr}");
+    assert_eq!(lines[0], "checksum: Checksum SHA1 mismatch for patch-Makefile");
+    assert_eq!(
+        lines[1],
+        "    expected: ab5ce8a374d3aca7948eecabc35386d8195e3fbf"
+    );
+    assert!(lines[2].starts_with("    actual:   "), "{stderr}");
+    assert_eq!(lines[3], "checksum: No checksum recorded for patch-extra");
+
+    fs::remove_file(patchdir.join("patch-Makefile"))?;
+    fs::remove_file(distdir.join("sub/stale.tar.gz"))?;
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--all")
+        .arg("-d")
+        .arg(&distdir)
+        .arg(&distinfo)
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(cmd.stderr, "".as_bytes());
+
+    /*
+     * --all requires at least one of -d or -P, and cannot be combined with
+     * explicit input files.
+     */
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--all")
+        .arg(&distinfo)
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(2));
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--all")
+        .arg("-d")
+        .arg(&distdir)
+        .arg(&distinfo)
+        .arg("digest1.txt")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(2));
+    Ok(())
+}
+
+/*
+ * With -k every mismatch is reported along with the expected and actual
+ * hashes, and the exit status reflects all results.
+ */
+#[test]
+fn test_checksum_keep_going() -> Result<()> {
+    let mut distinfo = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    distinfo.push("tests/data/distinfo.bad");
+
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("-k")
+        .arg(&distinfo)
+        .arg("digest1.txt")
+        .arg("digest2.txt")
+        .current_dir("tests/data")
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    let mismatches: Vec<&str> =
+        stderr.lines().filter(|l| l.starts_with("checksum: ")).collect();
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(cmd.stdout, "".as_bytes());
+    assert_eq!(
+        mismatches,
+        [
+            "checksum: Checksum BLAKE2s mismatch for digest1.txt",
+            "checksum: Checksum SHA512 mismatch for digest1.txt",
+            "checksum: Checksum BLAKE2s mismatch for digest2.txt",
+            "checksum: Checksum SHA512 mismatch for digest2.txt",
+        ]
+    );
+    assert!(stderr.contains("    expected: no\n"), "{stderr}");
+    assert!(stderr.contains("    expected: whatever\n"), "{stderr}");
+    assert!(
+        stderr.contains(
+            "    actual:   \
+             54020b13a41ebeebdbec3910e60c13b024568e597aed3c3412e611f703590311\n"
+        ),
+        "{stderr}"
+    );
+
+    /*
+     * A mismatch takes priority over an unrecorded file for the exit status,
+     * regardless of the order they are reported.
+     */
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--keep-going")
+        .arg("-p")
+        .arg(&distinfo)
+        .arg("patch-Makefile")
+        .arg("patch-unknown")
+        .current_dir("tests/data")
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    assert_eq!(cmd.status.code(), Some(1));
+    assert!(
+        stderr.ends_with("checksum: No checksum recorded for patch-unknown\n"),
+        "{stderr}"
+    );
+
+    /*
+     * Good files are still reported as such.
+     */
+    let mut distinfo = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    distinfo.push("tests/data/distinfo");
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("-k")
+        .arg(&distinfo)
+        .arg("digest1.txt")
+        .current_dir("tests/data")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(cmd.stderr, "".as_bytes());
+    Ok(())
+}
+
+/*
+ * Structured output.  Every result is a separate record on stdout, and the
+ * exit status is the same as for text output.
+ */
+#[test]
+fn test_checksum_format_json() -> Result<()> {
+    let mut distinfo = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    distinfo.push("tests/data/distinfo.bad");
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--format")
+        .arg("json")
+        .arg("-a")
+        .arg("BLAKE2s")
+        .arg(&distinfo)
+        .arg("digest1.txt")
+        .arg("nonexistent.txt")
+        .current_dir("tests/data")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(cmd.stderr, "".as_bytes());
+    let stdout = String::from_utf8_lossy(&cmd.stdout);
+    let records = stdout
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<std::result::Result<Vec<serde_json::Value>, _>>(
+    )?;
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        records[0],
+        serde_json::json!({
+            "filename": "digest1.txt",
+            "algorithm": "BLAKE2s",
+            "status": "mismatch",
+            "expected": "no",
+            "actual": "54020b13a41ebeebdbec3910e60c13b024568e597aed3c3412e611f703590311",
+            "error": null,
+        })
+    );
+    assert_eq!(records[1]["filename"], "nonexistent.txt");
+    assert_eq!(records[1]["status"], "unrecorded");
+    Ok(())
+}
+
+#[test]
+fn test_checksum_format_tap() -> Result<()> {
+    let mut distinfo = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    distinfo.push("tests/data/distinfo");
+    let output = "\
+TAP version 13
+1..3
+ok 1 - BLAKE2s digest1.txt
+ok 2 - SHA512 digest1.txt
+not ok 3 - nonexistent.txt
+  ---
+  status: unrecorded
+  ...
+";
+    let cmd = Command::new(MKTOOL)
+        .arg("checksum")
+        .arg("--format")
+        .arg("tap")
+        .arg(&distinfo)
+        .arg("digest1.txt")
+        .arg("nonexistent.txt")
+        .current_dir("tests/data")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&cmd.stdout), output);
+    assert_eq!(cmd.stderr, "".as_bytes());
+    Ok(())
+}
+
+/*
+ * With MKTOOL_CHECKSUM_CACHE set, hashes are recorded against the file
+ * metadata and trusted on later runs without reading the file.  Prove this
+ * by tampering with the cached hash, and then invalidate it by touching the
+ * file.
+ */
+#[test]
+fn test_checksum_cache() -> Result<()> {
+    let mut data = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    data.push("tests/data");
+    let tmpdir = tempfile::tempdir()?;
+    let distfile = tmpdir.path().join("digest1.txt");
+    let cachefile = tmpdir.path().join("cache");
+    fs::copy(data.join("digest1.txt"), &distfile)?;
+
+    let run = || {
+        Command::new(MKTOOL)
+            .arg("checksum")
+            .arg("-a")
+            .arg("SHA512")
+            .arg(data.join("distinfo"))
+            .arg(&distfile)
+            .env("MKTOOL_CHECKSUM_CACHE", &cachefile)
+            .output()
+    };
+
+    let cmd = run()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    let cache = fs::read_to_string(&cachefile)?;
+    assert_eq!(cache.lines().count(), 1);
+    assert!(cache.contains(" SHA512 ac6cd4956428e83c"), "{cache}");
+
+    fs::write(&cachefile, cache.replace(" SHA512 ac6cd", " SHA512 00000"))?;
+    let cmd = run()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(
+        cmd.stderr,
+        "checksum: Checksum SHA512 mismatch for digest1.txt\n".as_bytes()
+    );
+
+    let f = fs::File::options().write(true).open(&distfile)?;
+    f.set_modified(std::time::SystemTime::now())?;
+    drop(f);
+    let cmd = run()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(cmd.stderr, "".as_bytes());
+    Ok(())
+}
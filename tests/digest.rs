@@ -15,6 +15,7 @@
  */
 
 use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
 use std::process::Command;
 
 const MKTOOL: &str = env!("CARGO_BIN_EXE_mktool");
@@ -126,3 +127,247 @@ fn digest_stdin() -> Result<()> {
     assert!(stdout.trim().len() == 64, "expected SHA256 hex string: {stdout}");
     Ok(())
 }
+
+/*
+ * Verify that -c mode checks both our own output format and GNU coreutils
+ * format, with output and exit status compatible with "sha256sum -c".  The
+ * algorithm for coreutils lines is inferred from the hash length if needed.
+ */
+#[test]
+fn digest_check() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("good.txt"), b"hello\n")?;
+    std::fs::write(dir.path().join("bad.txt"), b"goodbye\n")?;
+
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "SHA256", "good.txt", "bad.txt"])
+        .current_dir(dir.path())
+        .output()?;
+    assert!(cmd.status.success());
+    std::fs::write(dir.path().join("sums"), &cmd.stdout)?;
+
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "-c", "sums"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "good.txt: OK\nbad.txt: OK\n"
+    );
+    assert_eq!(cmd.stderr, "".as_bytes());
+
+    /*
+     * GNU format, including a mismatch, a missing file, and a malformed
+     * line.
+     */
+    let sums = "\
+5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  good.txt
+0000000000000000000000000000000000000000000000000000000000000000 *bad.txt
+5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  missing.txt
+this is not a checksum
+";
+    std::fs::write(dir.path().join("sums"), sums)?;
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "SHA256", "-c", "sums"])
+        .current_dir(dir.path())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&cmd.stderr);
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "good.txt: OK\nbad.txt: FAILED\nmissing.txt: FAILED open or read\n"
+    );
+    assert!(stderr.contains("1 line is improperly formatted"), "{stderr}");
+    assert!(stderr.contains("1 listed file could not be read"), "{stderr}");
+    assert!(stderr.contains("1 computed checksum did NOT match"), "{stderr}");
+
+    /*
+     * Without an algorithm it is inferred from the hash length.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "-c", "sums"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "good.txt: OK\nbad.txt: FAILED\nmissing.txt: FAILED open or read\n"
+    );
+    let sums = "\
+b1946ac92492d2347c6235b4d2611184  good.txt
+f572d396fae9206628714fb2ce00f72e94f2258f  good.txt
+";
+    std::fs::write(dir.path().join("sums"), sums)?;
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "-c", "sums"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "good.txt: OK\ngood.txt: OK\n"
+    );
+
+    /*
+     * Other lengths are ambiguous and require an algorithm.
+     */
+    let sums = "\
+b1946ac92492d2347c6235b4d2611184  good.txt
+52d99a5e4f2ddb77ee0bfd2ccd1a8e2ee9fd6aa4f4f5d0e4ca0cf3a2  good.txt
+";
+    std::fs::write(dir.path().join("sums"), sums)?;
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "-c", "sums"])
+        .current_dir(dir.path())
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(cmd.stdout, "".as_bytes());
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stderr),
+        "digest: sums: algorithm required for untagged lines\n"
+    );
+    Ok(())
+}
+
+/*
+ * Multiple algorithms, either comma-separated or via repeated -a, produce
+ * one line per algorithm for each file in the order given.
+ */
+#[test]
+fn digest_multiple_algorithms() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("a.txt"), b"hello\n")?;
+    std::fs::write(dir.path().join("b.txt"), b"goodbye\n")?;
+    let output = "\
+SHA1 (a.txt) = f572d396fae9206628714fb2ce00f72e94f2258f
+MD5 (a.txt) = b1946ac92492d2347c6235b4d2611184
+SHA1 (b.txt) = e7d9b82b45d5833c9dada13f2379e7b66c823434
+MD5 (b.txt) = 32d6c11747e03715521007d8c84b5aff
+";
+
+    for args in [
+        vec!["digest", "SHA1,MD5", "a.txt", "b.txt"],
+        vec!["digest", "-a", "SHA1", "-a", "MD5", "a.txt", "b.txt"],
+        vec!["digest", "-a", "SHA1,MD5", "a.txt", "b.txt"],
+    ] {
+        let cmd = Command::new(MKTOOL)
+            .args(&args)
+            .current_dir(dir.path())
+            .output()?;
+        assert!(cmd.status.success(), "{args:?}");
+        assert_eq!(String::from_utf8_lossy(&cmd.stdout), output, "{args:?}");
+    }
+
+    /*
+     * With -a every argument is a file, even if it is named after an
+     * algorithm.
+     */
+    std::fs::write(dir.path().join("MD5"), b"hello\n")?;
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "-a", "SHA1", "MD5", "a.txt"])
+        .current_dir(dir.path())
+        .output()?;
+    assert!(cmd.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "SHA1 (MD5) = f572d396fae9206628714fb2ce00f72e94f2258f\n\
+         SHA1 (a.txt) = f572d396fae9206628714fb2ce00f72e94f2258f\n"
+    );
+
+    let mut child = Command::new(MKTOOL)
+        .args(["digest", "SHA1,MD5"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    child.stdin.take().ok_or("failed to open stdin")?.write_all(b"hello\n")?;
+    let output = child.wait_with_output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "f572d396fae9206628714fb2ce00f72e94f2258f\n\
+         b1946ac92492d2347c6235b4d2611184\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn digest_recursive() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let top = dir.path().join("top");
+    std::fs::create_dir_all(top.join("sub"))?;
+    std::fs::create_dir_all(top.join("empty"))?;
+    std::fs::write(top.join("a.txt"), b"hello\n")?;
+    std::fs::write(top.join("sub/b.txt"), b"goodbye\n")?;
+    std::os::unix::fs::symlink("a.txt", top.join("link"))?;
+
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "SHA1", "-r", "top"])
+        .current_dir(dir.path())
+        .output()?;
+    assert!(cmd.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "SHA1 (a.txt) = f572d396fae9206628714fb2ce00f72e94f2258f\n\
+         Symlink (link) -> a.txt\n\
+         SHA1 (sub/b.txt) = e7d9b82b45d5833c9dada13f2379e7b66c823434\n"
+    );
+
+    /*
+     * The tree digest is the hash of the manifest, and must not change
+     * unless the contents do.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "SHA1", "-r", "top", "-t"])
+        .current_dir(dir.path())
+        .output()?;
+    assert!(cmd.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "SHA1 (top) = b9978f48b01ccc8f92cc1af7c71184e75de8d505\n"
+    );
+    std::fs::write(top.join("sub/b.txt"), b"changed\n")?;
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "SHA1", "-r", "top", "-t"])
+        .current_dir(dir.path())
+        .output()?;
+    assert!(cmd.status.success());
+    assert_ne!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "SHA1 (top) = b9978f48b01ccc8f92cc1af7c71184e75de8d505\n"
+    );
+
+    /*
+     * A single file is listed relative to its parent.
+     */
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "SHA1", "-r", "top/a.txt"])
+        .current_dir(dir.path())
+        .output()?;
+    assert!(cmd.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&cmd.stdout),
+        "SHA1 (a.txt) = f572d396fae9206628714fb2ce00f72e94f2258f\n"
+    );
+
+    /*
+     * No tree digest is printed if any file could not be read.
+     */
+    std::fs::create_dir(top.join("locked"))?;
+    std::fs::write(top.join("locked/c.txt"), b"locked\n")?;
+    std::fs::set_permissions(
+        top.join("locked/c.txt"),
+        std::fs::Permissions::from_mode(0o000),
+    )?;
+    let unreadable = std::fs::read(top.join("locked/c.txt")).is_err();
+    let cmd = Command::new(MKTOOL)
+        .args(["digest", "SHA1", "-r", "top", "-t"])
+        .current_dir(dir.path())
+        .output()?;
+    if unreadable {
+        assert_eq!(cmd.status.code(), Some(1));
+        assert_eq!(cmd.stdout, "".as_bytes());
+        assert!(String::from_utf8_lossy(&cmd.stderr).contains("c.txt"));
+    }
+    Ok(())
+}
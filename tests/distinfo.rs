@@ -87,6 +87,54 @@ fn test_distinfo_invalid_distinfo() -> Result<()> {
     Ok(())
 }
 
+/*
+ * Algorithms that pkgsrc::digest does not support.  Running again against
+ * the generated distinfo must find it unchanged, so the new checksums have
+ * to be read back in as well as written.
+ */
+#[test]
+fn test_distinfo_new_algorithms() -> Result<()> {
+    let output = format!(
+        "{}\n\n{}\n{}\n{}\n{}\n",
+        "$NetBSD$",
+        "BLAKE3 (digest1.txt) = 9df920192a5c84e2e75a97bb4a7adc475f7be49ced71d756027c8fbb9f1ec30b",
+        "SHA3-256 (digest1.txt) = f67b5bfbba6330577590a9d36a54a66ea24ee9bac5c4778cae6aa1b0ce46594a",
+        "Size (digest1.txt) = 159 bytes",
+        "SHA3-256 (patch-Makefile) = 5c8a0b506934f218a8f8e87ef5297b0bbee72c5b5788e89785d49191a5785742",
+    );
+    let args = [
+        "distinfo",
+        "-a",
+        "BLAKE3",
+        "-a",
+        "sha3-256",
+        "-c",
+        "digest1.txt",
+        "-p",
+        "SHA3-256",
+        "patch-Makefile",
+    ];
+    let cmd =
+        Command::new(MKTOOL).args(args).current_dir("tests/data").output()?;
+    assert_eq!(cmd.status.code(), Some(1));
+    assert_eq!(cmd.stdout, output.as_bytes());
+    assert_eq!(cmd.stderr, "".as_bytes());
+
+    let dir = tempfile::tempdir()?;
+    let distinfo = dir.path().join("distinfo");
+    fs::write(&distinfo, &output)?;
+    let cmd = Command::new(MKTOOL)
+        .args(args)
+        .arg("-f")
+        .arg(&distinfo)
+        .current_dir("tests/data")
+        .output()?;
+    assert_eq!(cmd.status.code(), Some(0));
+    assert_eq!(cmd.stdout, output.as_bytes());
+    assert_eq!(cmd.stderr, "".as_bytes());
+    Ok(())
+}
+
 /*
  * Specify a distfile but no checksums, should only print size (and retain
  * existing patch entries).
@@ -385,6 +385,71 @@ fn fetch_http_404() -> Result<()> {
     Ok(())
 }
 
+/*
+ * Downloads are verified against checksums using algorithms that
+ * pkgsrc::digest does not support, first with a mismatch that must be
+ * rejected, and then with the correct checksums.
+ */
+#[test]
+fn fetch_http_new_algorithms() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let distdir = dir.path().to_str().ok_or("invalid tempdir path")?;
+    let distinfo = dir.path().join("distinfo");
+    let blake3 =
+        "8e4c7c1b99dbfd50e7a95185fead5ee1448fa904a2fdd778eaf5f2dbfd629a99";
+    let sha3 =
+        "b314e28493eae9dab57ac4f0c6d887bddbbeb810e900d818395ace558e96516d";
+
+    for (hash, ok) in [(&"0".repeat(64), false), (&blake3.to_string(), true)] {
+        fs::write(
+            &distinfo,
+            format!(
+                "BLAKE3 (test.txt) = {hash}\n\
+                 SHA3-256 (test.txt) = {sha3}\n\
+                 Size (test.txt) = 6 bytes\n"
+            ),
+        )?;
+        let port = mock_server(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhello\n",
+            true,
+        )?;
+        let input =
+            format!("test.txt {distdir} -http://127.0.0.1:{port}/test.txt\n");
+
+        let mut child = Command::new(MKTOOL)
+            .args([
+                "fetch",
+                "-d",
+                distdir,
+                "-f",
+                distinfo.to_str().ok_or("invalid distinfo path")?,
+                "-I",
+                "-",
+            ])
+            .env("MKTOOL_JOBS", "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().ok_or("failed to open stdin")?;
+        stdin.write_all(input.as_bytes())?;
+        drop(stdin);
+
+        let output = child.wait_with_output()?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.success(), ok, "{stderr}");
+        assert_eq!(
+            stderr.contains("Checksum BLAKE3 mismatch"),
+            !ok,
+            "{stderr}"
+        );
+        assert_eq!(dir.path().join("test.txt").exists(), ok);
+        assert!(!has_temp_files(dir.path())?, "temp file not cleaned up");
+    }
+    Ok(())
+}
+
 /*
  * Verify that re-fetching an already-downloaded file is a no-op.
  */